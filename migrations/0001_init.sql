@@ -1,6 +1,4 @@
-PRAGMA foreign_keys = ON;
-
-CREATE TABLE User (
+CREATE TABLE IF NOT EXISTS User (
     id TEXT PRIMARY KEY,
     username TEXT NOT NULL,
     discriminator TEXT NOT NULL,
@@ -11,7 +9,7 @@ CREATE TABLE User (
     last_question_reset DATE
 );
 
-CREATE TABLE Session (
+CREATE TABLE IF NOT EXISTS Session (
     id TEXT PRIMARY KEY,
     user_id TEXT NOT NULL,
     access_token TEXT NOT NULL,
@@ -25,7 +23,7 @@ CREATE TABLE Session (
 );
 
 
-CREATE TABLE Question (
+CREATE TABLE IF NOT EXISTS Question (
     id TEXT PRIMARY KEY,
     title TEXT NOT NULL,
     body TEXT NOT NULL,
@@ -37,7 +35,7 @@ CREATE TABLE Question (
         ON UPDATE NO ACTION
 );
 
-CREATE TABLE Answer (
+CREATE TABLE IF NOT EXISTS Answer (
     id TEXT PRIMARY KEY,
     body TEXT NOT NULL,
     created_at DATETIME NOT NULL,
@@ -51,8 +49,8 @@ CREATE TABLE Answer (
         ON UPDATE NO ACTION
 );
 
-CREATE INDEX idx_question_user_id ON Question(user_id);
-CREATE INDEX idx_question_created_at ON Question(created_at);
-CREATE INDEX idx_session_user_id ON Session(user_id);
-CREATE INDEX idx_answer_user_id ON Answer(user_id);
-CREATE INDEX idx_answer_question_id ON Answer(question_id);
+CREATE INDEX IF NOT EXISTS idx_question_user_id ON Question(user_id);
+CREATE INDEX IF NOT EXISTS idx_question_created_at ON Question(created_at);
+CREATE INDEX IF NOT EXISTS idx_session_user_id ON Session(user_id);
+CREATE INDEX IF NOT EXISTS idx_answer_user_id ON Answer(user_id);
+CREATE INDEX IF NOT EXISTS idx_answer_question_id ON Answer(question_id);
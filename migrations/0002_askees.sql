@@ -0,0 +1,11 @@
+-- Users who accept questions, and the askee each question is routed to.
+-- Existing questions were all asked to the original admin.
+ALTER TABLE User ADD COLUMN is_askee BOOLEAN NOT NULL DEFAULT 0 CHECK (is_askee IN (0, 1));
+UPDATE User SET is_askee = is_admin;
+
+ALTER TABLE Question ADD COLUMN askee_id TEXT REFERENCES User(id) ON DELETE CASCADE ON UPDATE NO ACTION;
+UPDATE Question SET askee_id = (
+    SELECT id FROM User WHERE is_admin = 1 ORDER BY joined_at LIMIT 1
+);
+
+CREATE INDEX idx_question_askee_id ON Question(askee_id);
@@ -0,0 +1,3 @@
+-- Discord notifications sent to askers when their question is answered
+ALTER TABLE User ADD COLUMN notify_answers BOOLEAN NOT NULL DEFAULT 1 CHECK (notify_answers IN (0, 1));
+ALTER TABLE Question ADD COLUMN channel_id TEXT;
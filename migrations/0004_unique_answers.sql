@@ -0,0 +1,8 @@
+-- A question has at most one answer. Keep the first one if a race ever stored two.
+DELETE FROM Answer
+WHERE rowid NOT IN (
+    SELECT MIN(rowid) FROM Answer GROUP BY question_id
+);
+
+DROP INDEX IF EXISTS idx_answer_question_id;
+CREATE UNIQUE INDEX idx_answer_question_id ON Answer(question_id);
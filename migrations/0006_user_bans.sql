@@ -0,0 +1,2 @@
+-- Banned users cannot sign in, use the API or ask questions
+ALTER TABLE User ADD COLUMN banned BOOLEAN NOT NULL DEFAULT 0 CHECK (banned IN (0, 1));
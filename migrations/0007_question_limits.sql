@@ -0,0 +1,25 @@
+-- Per user override of the daily limit, NULL falls back to the role limit and then the default
+ALTER TABLE User ADD COLUMN daily_limit INTEGER;
+-- Keep the limits an admin changed away from the old default of 10
+UPDATE User SET daily_limit = daily_questions WHERE daily_questions != 10;
+ALTER TABLE User DROP COLUMN daily_questions;
+
+-- Extra questions granted by an admin, spent once the daily limit is used up
+ALTER TABLE User ADD COLUMN bonus_questions INTEGER NOT NULL DEFAULT 0;
+-- Questions asked on the day stored in last_question_reset. Counters start at zero on deploy.
+ALTER TABLE User ADD COLUMN questions_used INTEGER NOT NULL DEFAULT 0;
+
+-- Daily limit for everyone with a role (user, askee or admin) and no override of their own
+CREATE TABLE RoleLimit (
+    role TEXT PRIMARY KEY,
+    daily_limit INTEGER NOT NULL
+);
+
+-- Temporarily raises everyone's daily limit
+CREATE TABLE LimitBoost (
+    id TEXT PRIMARY KEY,
+    extra_questions INTEGER NOT NULL,
+    ends_at DATETIME NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
@@ -0,0 +1,2 @@
+-- IANA timezone the user's daily quota resets in, NULL uses QUOTA_TIMEZONE
+ALTER TABLE User ADD COLUMN timezone TEXT;
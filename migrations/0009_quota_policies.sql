@@ -0,0 +1,6 @@
+-- What each question took out of the asker's quota
+ALTER TABLE Question ADD COLUMN cost INTEGER NOT NULL DEFAULT 1;
+-- Burst policy state, when the asker's quota will be completely refilled
+ALTER TABLE User ADD COLUMN quota_tat DATETIME;
+
+CREATE INDEX idx_question_user_id_created_at ON Question(user_id, created_at);
@@ -0,0 +1,2 @@
+-- Users without an avatar were stored with an empty hash, NULL now means Discord's default
+UPDATE User SET avatar = NULL WHERE avatar = '';
@@ -0,0 +1,4 @@
+-- Sessions are per device now, these describe each one on the sessions page
+ALTER TABLE Session ADD COLUMN user_agent TEXT;
+ALTER TABLE Session ADD COLUMN created_at DATETIME;
+ALTER TABLE Session ADD COLUMN last_seen_at DATETIME;
@@ -0,0 +1,5 @@
+-- Last claim of each job shared between processes, so only one of them runs it
+CREATE TABLE JobRun (
+    name TEXT PRIMARY KEY,
+    claimed_at DATETIME NOT NULL
+);
@@ -1,34 +1,60 @@
+use crate::config;
 use crate::crypto::decrypt;
 use crate::db::Session;
 use crate::error::{Error, Result};
+use crate::handlers::discord::DiscordUser;
 use oauth2::basic::BasicClient;
 use oauth2::reqwest::async_http_client;
 use oauth2::{RefreshToken, TokenResponse};
 
-pub fn oauth_client() -> Result<BasicClient> {
-    let client_id = std::env::var("DISCORD_CLIENT_ID")
-        .map_err(|_| Error::Config("Failed to get DISCORD_CLIENT_ID from env".to_string()))?;
-    let client_secret = std::env::var("DISCORD_CLIENT_SECRET")
-        .map_err(|_| Error::Config("Failed to get DISCORD_CLIENT_SECRET from env".to_string()))?;
-    let redirect_uri = std::env::var("DISCORD_REDIRECT_URI")
-        .map_err(|_| Error::Config("Failed to get DISCORD_REDIRECT_URI from env".to_string()))?;
-
-    Ok(BasicClient::new(
-        oauth2::ClientId::new(client_id),
-        Some(oauth2::ClientSecret::new(client_secret)),
+/// Without a redirect URI, as in a bot-only process, the client cannot sign anyone in
+pub fn oauth_client(config: &config::Discord) -> Result<BasicClient> {
+    let client = BasicClient::new(
+        oauth2::ClientId::new(config.client_id.clone()),
+        Some(oauth2::ClientSecret::new(config.client_secret.clone())),
         oauth2::AuthUrl::new(
             "https://discord.com/api/oauth2/authorize?response_type=code".to_string(),
         )
         .unwrap(),
         Some(oauth2::TokenUrl::new("https://discord.com/api/oauth2/token".to_string()).unwrap()),
-    )
-    .set_redirect_uri(oauth2::RedirectUrl::new(redirect_uri).unwrap()))
+    );
+    if config.redirect_uri.is_empty() {
+        return Ok(client);
+    }
+
+    let redirect_uri = oauth2::RedirectUrl::new(config.redirect_uri.clone()).map_err(|e| {
+        Error::Config(format!(
+            "Invalid DISCORD_REDIRECT_URI {}: {}",
+            config.redirect_uri, e
+        ))
+    })?;
+    Ok(client.set_redirect_uri(redirect_uri))
+}
+
+/// The profile of the Discord user the access token belongs to
+pub async fn get_discord_user(http: &reqwest::Client, access_token: &str) -> Result<DiscordUser> {
+    http.get("https://discordapp.com/api/users/@me")
+        .bearer_auth(access_token)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| Error::Auth(e.to_string()))?
+        .json::<DiscordUser>()
+        .await
+        .map_err(|e| Error::Auth(e.to_string()))
 }
 
-pub async fn refresh_access_token(client: &BasicClient, session: &Session) -> Result<Session> {
-    let encryption_key = crate::crypto::get_key();
+/// Exchanges the session's refresh token for new tokens, and fetches the user's profile with
+/// them so name and avatar changes are picked up. A profile that cannot be fetched does not fail
+/// the refresh.
+pub async fn refresh_access_token(
+    client: &BasicClient,
+    http: &reqwest::Client,
+    encryption_key: &[u8; 32],
+    session: &Session,
+) -> Result<(Session, Option<DiscordUser>)> {
     let refresh_token_plain = decrypt(
-        &encryption_key,
+        encryption_key,
         &session.refresh_token,
         &session.refresh_token_nonce,
     );
@@ -46,15 +72,14 @@ pub async fn refresh_access_token(client: &BasicClient, session: &Session) -> Re
             let expires_in = token.expires_in().map(|d| d.as_secs()).unwrap_or(3600);
             let expires_at = chrono::Utc::now() + chrono::Duration::seconds(expires_in as i64);
 
-            let encryption_key = crate::crypto::get_key();
             let access_token_plain = token.access_token().secret();
             let (access_token, access_token_nonce) =
-                crate::crypto::encrypt(&encryption_key, access_token_plain);
+                crate::crypto::encrypt(encryption_key, access_token_plain);
             assert_ne!(access_token, *access_token_plain);
 
             let refresh_token_plain = token.refresh_token().expect("refresh token").secret();
             let (refresh_token, refresh_token_nonce) =
-                crate::crypto::encrypt(&encryption_key, refresh_token_plain);
+                crate::crypto::encrypt(encryption_key, refresh_token_plain);
 
             let new_session = Session {
                 id: session.id.clone(),
@@ -64,9 +89,20 @@ pub async fn refresh_access_token(client: &BasicClient, session: &Session) -> Re
                 refresh_token: refresh_token.clone(),
                 refresh_token_nonce: refresh_token_nonce.clone(),
                 expires_at,
+                user_agent: session.user_agent.clone(),
+                created_at: session.created_at,
+                last_seen_at: session.last_seen_at,
             };
 
-            Ok(new_session)
+            let profile = match get_discord_user(http, access_token_plain).await {
+                Ok(profile) => Some(profile),
+                Err(e) => {
+                    tracing::warn!("Failed to refresh profile of {}: {:?}", session.user_id, e);
+                    None
+                }
+            };
+
+            Ok((new_session, profile))
         }
         Err(err) => {
             // Handle error (e.g., log it)
@@ -74,3 +110,92 @@ pub async fn refresh_access_token(client: &BasicClient, session: &Session) -> Re
         }
     }
 }
+
+/// What a request is allowed to do through the JSON API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Read the user's questions, answers and quota
+    Read,
+    /// Submit new questions
+    Ask,
+    /// Answer questions sent to the user
+    Answer,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Read, Scope::Ask, Scope::Answer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Ask => "ask",
+            Scope::Answer => "answer",
+        }
+    }
+}
+
+/// Scopes granted to the current request. Browser sessions get every scope, API tokens only the
+/// ones they were minted with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scopes(Vec<Scope>);
+
+impl Scopes {
+    pub fn all() -> Self {
+        Self(Scope::ALL.to_vec())
+    }
+
+    pub fn new(scopes: Vec<Scope>) -> Self {
+        Self(scopes)
+    }
+
+    /// Parses the space separated form stored in the database, ignoring unknown scopes
+    pub fn parse(scopes: &str) -> Self {
+        Self(
+            Scope::ALL
+                .into_iter()
+                .filter(|scope| scopes.split_whitespace().any(|s| s == scope.as_str()))
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, scope: Scope) -> bool {
+        self.0.contains(&scope)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<()> {
+        if self.contains(scope) {
+            Ok(())
+        } else {
+            Err(Error::MissingScope)
+        }
+    }
+}
+
+impl std::fmt::Display for Scopes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scopes = self.0.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+        write!(f, "{}", scopes.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes_round_trip() {
+        let scopes = Scopes::new(vec![Scope::Read, Scope::Answer]);
+        assert_eq!(scopes.to_string(), "read answer");
+        assert_eq!(Scopes::parse(&scopes.to_string()), scopes);
+
+        let parsed = Scopes::parse("ask admin");
+        assert!(parsed.contains(Scope::Ask));
+        assert!(!parsed.contains(Scope::Read));
+        assert!(parsed.require(Scope::Read).is_err());
+        assert!(Scopes::parse("").is_empty());
+    }
+}
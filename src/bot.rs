@@ -1,33 +1,279 @@
-use crate::{oai, ADMIN_ID};
-use serenity::all::{Context, EventHandler, Message};
+use crate::{
+    db,
+    error::{Error, Result},
+    handlers::{
+        app::{ask_question, NewQuestionForm},
+        discord::{get_or_create_user, DiscordUser},
+    },
+    oai,
+    shutdown::Shutdown,
+    AppState, SITE_URL,
+};
+use serenity::all::{ActivityData, Client, GatewayIntents, OnlineStatus};
+use serenity::all::{ChannelId, CreateAllowedMentions, CreateMessage, UserId};
+use serenity::all::{
+    Command, CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, EventHandler, Interaction,
+    Message, Ready, ResolvedValue,
+};
 use serenity::async_trait;
-use tracing::info;
+use serenity::http::Http;
+use tracing::{error, info};
 
 #[derive(Clone)]
 pub struct Handler {
-    oai: oai::Client,
+    /// Decides whether a message mentioning an askee is a question, replies are off without it
+    oai: Option<oai::Client>,
+    state: AppState,
 }
 
 impl Handler {
-    pub fn new(oai: oai::Client) -> Self {
-        Self { oai }
+    pub fn new(oai: Option<oai::Client>, state: AppState) -> Self {
+        Self { oai, state }
+    }
+
+    /// Creates a question from the options of an `/ask` command
+    async fn ask(&self, command: &CommandInteraction) -> Result<(db::Question, db::User)> {
+        let mut title = String::new();
+        let mut body = None;
+        let mut public = false;
+        let mut askee_id = None;
+        for option in command.data.options() {
+            match (option.name, option.value) {
+                ("title", ResolvedValue::String(value)) => title = value.to_string(),
+                ("body", ResolvedValue::String(value)) => body = Some(value.to_string()),
+                ("public", ResolvedValue::Boolean(value)) => public = value,
+                ("to", ResolvedValue::User(user, _)) => askee_id = Some(user.id.to_string()),
+                _ => {}
+            }
+        }
+
+        let author = &command.user;
+        let user_data = DiscordUser {
+            id: author.id.to_string(),
+            avatar: author.avatar.map(|hash| hash.to_string()),
+            username: author.name.clone(),
+            discriminator: author
+                .discriminator
+                .map(|d| d.to_string())
+                .unwrap_or_else(|| "0".to_string()),
+            email: None,
+        };
+        let user = get_or_create_user(&self.state, &user_data).await?;
+
+        // Without an explicit askee, default to the only one accepting questions
+        let askee_id = match askee_id {
+            Some(id) => id,
+            None => {
+                let askees = self
+                    .state
+                    .db
+                    .get_askees()
+                    .await?
+                    .into_iter()
+                    .filter(|askee| askee.id != user.id)
+                    .collect::<Vec<_>>();
+                match askees.as_slice() {
+                    [askee] => askee.id.clone(),
+                    _ => return Err(Error::InvalidAskee),
+                }
+            }
+        };
+        let askee = self
+            .state
+            .db
+            .get_user(&askee_id)
+            .await?
+            .ok_or(Error::InvalidAskee)?;
+
+        let form = NewQuestionForm {
+            title,
+            body,
+            public,
+            askee_id,
+        };
+        let channel_id = Some(command.channel_id.to_string());
+        let question = ask_question(&self.state, &user, form, channel_id).await?;
+        Ok((question, askee))
+    }
+}
+
+/// Builds the gateway client, with the LLM classifier when it is turned on
+pub async fn client(token: &str, state: AppState) -> serenity::Result<Client> {
+    let config = &state.config;
+    let oai = match (&config.openai_api_key, config.features.llm_classifier) {
+        (Some(key), true) => Some(oai::Client::new(key)),
+        _ => None,
+    };
+    let intents = GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::DIRECT_MESSAGES
+        | GatewayIntents::MESSAGE_CONTENT;
+
+    let activity = ActivityData::watching("nbols");
+    Client::builder(token, intents)
+        .status(OnlineStatus::DoNotDisturb)
+        .activity(activity)
+        .event_handler(Handler::new(oai, state.clone()))
+        .await
+}
+
+/// Connects to the gateway and handles events until the connection is lost or the process
+/// shuts down
+pub async fn run(mut client: Client, shutdown: Shutdown) {
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        shutdown.requested().await;
+        shard_manager.shutdown_all().await;
+    });
+
+    // Start listening for events by starting a single shard
+    match client.start().await {
+        Ok(_) => info!("Discord bot disconnected"),
+        Err(why) => error!("Client error: {why:?}"),
     }
 }
 
+/// Tells the asker their question was answered. Public questions asked from a channel are
+/// answered in that channel, everything else goes to the asker's DMs.
+pub async fn notify_answered(
+    http: &Http,
+    asker: &db::User,
+    askee: &db::User,
+    question: &db::Question,
+    answer: &db::Answer,
+) -> serenity::Result<()> {
+    let content = format!(
+        "<@{}> {} answered your question **{}**\n> {}\n{}/app",
+        asker.id, askee.username, question.title, answer.body, SITE_URL
+    );
+
+    let asker_id = asker
+        .id
+        .parse::<u64>()
+        .ok()
+        .filter(|id| *id != 0)
+        .map(UserId::new)
+        .ok_or(serenity::Error::Other("Invalid asker id"))?;
+    // The title and the answer are user input, only the asker may get pinged
+    let message = CreateMessage::new()
+        .content(content)
+        .allowed_mentions(CreateAllowedMentions::new().users([asker_id]));
+
+    let channel_id = question
+        .channel_id
+        .as_deref()
+        .filter(|_| question.public)
+        .and_then(|id| id.parse::<u64>().ok())
+        .filter(|id| *id != 0);
+    match channel_id {
+        Some(channel_id) => {
+            ChannelId::new(channel_id)
+                .send_message(http, message)
+                .await?;
+        }
+        None => {
+            asker_id.direct_message(http, message).await?;
+        }
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl EventHandler for Handler {
-    async fn message(&self, ctx: Context, msg: Message) {
-        let serialized = serde_json::to_string(&msg).unwrap();
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        info!("Discord bot connected as {}", ready.user.name);
 
-        let admin_mentioned = msg.mentions.iter().any(|m| m.id == ADMIN_ID);
+        let command = CreateCommand::new("ask")
+            .description("Ask a question")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "title", "Your question")
+                    .required(true)
+                    .min_length(5)
+                    .max_length(100),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "public",
+                    "Show the question and its answer to everyone",
+                )
+                .required(true),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "body", "More details")
+                    .max_length(1000),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "to",
+                "Who to ask, required when several people accept questions",
+            ));
 
-        if !admin_mentioned {
+        if let Err(e) = Command::create_global_command(&ctx.http, command).await {
+            error!("Failed to register /ask command: {:?}", e);
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let Interaction::Command(command) = interaction else {
+            return;
+        };
+        if command.data.name != "ask" {
             return;
         }
 
-        let res = self
-            .oai
-            .create_chat_completion(oai::Model::Gpt4oMini, serialized)
+        let content = match self.ask(&command).await {
+            Ok((question, askee)) => {
+                format!(
+                    "Question sent to {}: **{}**",
+                    askee.username, question.title
+                )
+            }
+            Err(e) => {
+                error!("Error: {:?}", e);
+                e.status_and_message().1.to_string()
+            }
+        };
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(content)
+                .allowed_mentions(CreateAllowedMentions::new())
+                .ephemeral(true),
+        );
+        if let Err(e) = command.create_response(&ctx.http, response).await {
+            error!("Failed to respond to /ask: {:?}", e);
+        }
+    }
+
+    async fn message(&self, ctx: Context, msg: Message) {
+        let Some(oai) = &self.oai else {
+            return;
+        };
+        if msg.mentions.is_empty() {
+            return;
+        }
+
+        let askees = match self.state.db.get_askees().await {
+            Ok(askees) => askees,
+            Err(e) => {
+                error!("Failed to get askees: {:?}", e);
+                return;
+            }
+        };
+
+        let askee = askees
+            .iter()
+            .find(|askee| msg.mentions.iter().any(|m| m.id.to_string() == askee.id));
+
+        let Some(askee) = askee else {
+            return;
+        };
+
+        let serialized = serde_json::to_string(&msg).unwrap();
+
+        let res = oai
+            .create_chat_completion(oai::Model::Gpt4oMini, &askee.id, serialized)
             .await;
 
         info!(
@@ -48,8 +294,14 @@ impl EventHandler for Handler {
         if !is_question {
             return;
         }
-        let reply = format!("<@{}> [ask lp](https://ask-lp.com)", user.id);
+        let reply = format!(
+            "<@{}> [ask {}]({}/app/question/new?askee={})",
+            user.id, askee.username, SITE_URL, askee.id
+        );
 
-        msg.channel_id.say(ctx, reply).await.ok();
+        let message = CreateMessage::new()
+            .content(reply)
+            .allowed_mentions(CreateAllowedMentions::new().users([user.id]));
+        msg.channel_id.send_message(&ctx, message).await.ok();
     }
 }
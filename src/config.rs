@@ -0,0 +1,772 @@
+use crate::{
+    db,
+    error::{Error, Result},
+    quota::{QuestionCost, QuotaPolicy},
+    time,
+};
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use chrono_tz::Tz;
+use lettre::message::Mailbox;
+use oauth2::url::Url;
+use std::{collections::HashMap, path::Path, str::FromStr};
+use tracing::warn;
+
+/// Read when `CONFIG_FILE` does not point somewhere else, if it exists
+pub const DEFAULT_CONFIG_FILE: &str = "asklp.toml";
+
+/// Every setting, as its env var and its key in the config file. Env vars win over the file.
+const SETTINGS: &[(&str, &str)] = &[
+    ("RUN_MODE", "mode"),
+    ("PORT", "port"),
+    ("ENCRYPTION_KEY", "encryption_key"),
+    ("ADMIN_IDS", "admin_ids"),
+    ("DATABASE_MODE", "database.mode"),
+    ("DATABASE_PATH", "database.path"),
+    ("LIBSQL_URL", "database.url"),
+    ("LIBSQL_TOKEN", "database.token"),
+    ("DISCORD_CLIENT_ID", "discord.client_id"),
+    ("DISCORD_CLIENT_SECRET", "discord.client_secret"),
+    ("DISCORD_REDIRECT_URI", "discord.redirect_uri"),
+    ("DISCORD_BOT_TOKEN", "discord.bot_token"),
+    ("OPENAI_API_KEY", "openai.api_key"),
+    ("QUOTA_TIMEZONE", "quota.timezone"),
+    ("QUOTA_POLICY", "quota.policy"),
+    ("QUESTION_COST_PUBLIC", "quota.cost_public"),
+    ("QUESTION_COST_PRIVATE", "quota.cost_private"),
+    ("QUESTION_COST_LONG", "quota.cost_long"),
+    ("QUESTION_LONG_BODY", "quota.long_body"),
+    ("NOTIFIERS", "notify.notifiers"),
+    ("TWILIO_ACCOUNT_SID", "twilio.account_sid"),
+    ("TWILIO_AUTH_TOKEN", "twilio.auth_token"),
+    ("TWILIO_MESSAGE_SERVICE_SID", "twilio.message_service_sid"),
+    ("TWILIO_TO", "twilio.to"),
+    ("TWILIO_ASKEE_ID", "twilio.askee_id"),
+    ("WEBHOOK_URL", "webhook.url"),
+    ("SMTP_HOST", "smtp.host"),
+    ("SMTP_USERNAME", "smtp.username"),
+    ("SMTP_PASSWORD", "smtp.password"),
+    ("SMTP_FROM", "smtp.from"),
+    ("SMTP_TO", "smtp.to"),
+    ("SMTP_ASKEE_ID", "smtp.askee_id"),
+    ("FEATURE_BOT", "features.bot"),
+    ("FEATURE_SMS", "features.sms"),
+    ("FEATURE_LLM_CLASSIFIER", "features.llm_classifier"),
+];
+
+/// Everything the app is configured with, loaded and validated once at startup
+pub struct Config {
+    pub mode: Mode,
+    pub port: u16,
+    pub database: db::Location,
+    /// Encrypts the Discord tokens at rest and signs cookies. Random in a bot-only process
+    /// without `ENCRYPTION_KEY`, which neither stores tokens nor sets cookies.
+    pub encryption_key: [u8; 32],
+    pub discord: Discord,
+    pub openai_api_key: Option<String>,
+    /// Discord ids of the users who become admins (and askees) when they sign up
+    pub admin_ids: Vec<String>,
+    /// Where daily quotas reset at midnight for users without a timezone of their own
+    pub quota_timezone: Tz,
+    pub quota_policy: QuotaPolicy,
+    pub question_cost: QuestionCost,
+    /// Notification backends, in the order of `NOTIFIERS`
+    pub notifiers: Vec<Notifier>,
+    pub features: Features,
+}
+
+/// The OAuth client settings are empty in a bot-only process, only the web app signs people in
+pub struct Discord {
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    /// Only required by the bot and the Discord notifier
+    pub bot_token: Option<String>,
+}
+
+pub enum Notifier {
+    Twilio(Twilio),
+    Webhook {
+        url: String,
+    },
+    Email(Smtp),
+    /// Messages through the bot, which needs `DISCORD_BOT_TOKEN`
+    Discord,
+}
+
+pub struct Twilio {
+    pub account_sid: String,
+    pub auth_token: String,
+    pub message_service_sid: String,
+    pub to: String,
+    /// Discord id of the askee `to` belongs to, nobody else is notified by SMS
+    pub askee_id: String,
+}
+
+pub struct Smtp {
+    pub host: String,
+    pub username: String,
+    pub password: String,
+    pub from: Mailbox,
+    pub to: Mailbox,
+    /// Discord id of the askee `to` belongs to, nobody else is notified by email
+    pub askee_id: String,
+}
+
+/// Which parts of the app this process runs, so the web side can scale apart from the bot's
+/// single gateway connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// The web app, the API and the background jobs
+    Serve,
+    /// The Discord bot
+    Bot,
+    /// Everything in one process
+    All,
+}
+
+impl Mode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "serve" => Some(Mode::Serve),
+            "bot" => Some(Mode::Bot),
+            "all" => Some(Mode::All),
+            _ => None,
+        }
+    }
+}
+
+/// Parts of the app that can be turned off, all on by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    /// The Discord bot behind `/ask` and the replies to mentions
+    pub bot: bool,
+    /// The Twilio notifier
+    pub sms: bool,
+    /// Asking OpenAI whether a message mentioning an askee is a question
+    pub llm_classifier: bool,
+}
+
+impl Config {
+    /// Reads the env and the config file at `CONFIG_FILE`, or `asklp.toml` when present. A mode
+    /// picked on the command line wins over `RUN_MODE`.
+    pub fn load(mode: Option<Mode>) -> Result<Self> {
+        let file = match std::env::var("CONFIG_FILE") {
+            Ok(path) => read_file(&path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => read_file(DEFAULT_CONFIG_FILE)?,
+            Err(_) => toml::Table::new(),
+        };
+        Self::from_sources(std::env::vars().collect(), file, mode)
+    }
+
+    pub fn runs_server(&self) -> bool {
+        self.mode != Mode::Bot
+    }
+
+    pub fn runs_bot(&self) -> bool {
+        self.mode != Mode::Serve && self.features.bot
+    }
+
+    /// Validates every setting, reporting all the problems at once
+    pub fn from_sources(
+        env: HashMap<String, String>,
+        file: toml::Table,
+        mode: Option<Mode>,
+    ) -> Result<Self> {
+        let mut settings = Settings {
+            env,
+            file,
+            errors: Vec::new(),
+        };
+        settings.check_file_keys();
+
+        let features = Features {
+            bot: settings.flag("FEATURE_BOT"),
+            sms: settings.flag("FEATURE_SMS"),
+            llm_classifier: settings.flag("FEATURE_LLM_CLASSIFIER"),
+        };
+
+        let mode = mode.unwrap_or_else(|| match settings.get("RUN_MODE") {
+            Some(name) => Mode::parse(&name).unwrap_or_else(|| {
+                settings.error(format!(
+                    "Invalid RUN_MODE {}, expected serve, bot or all",
+                    name
+                ));
+                Mode::All
+            }),
+            None => Mode::All,
+        });
+        if mode == Mode::Bot && !features.bot {
+            settings.error("RUN_MODE is bot but the bot is turned off with FEATURE_BOT");
+        }
+        let runs_bot = mode != Mode::Serve && features.bot;
+        let runs_server = mode != Mode::Bot;
+
+        let default_port = if cfg!(debug_assertions) { 3000 } else { 8080 };
+        let port = settings.parse("PORT").unwrap_or(default_port);
+        let encryption_key = settings.encryption_key(runs_server);
+        let database = settings.database();
+
+        let bot_token = settings.get("DISCORD_BOT_TOKEN");
+        if runs_bot && bot_token.is_none() {
+            settings.error(
+                "DISCORD_BOT_TOKEN is required by the bot, turn it off with FEATURE_BOT=false",
+            );
+        }
+        let redirect_uri = settings.required_by_server("DISCORD_REDIRECT_URI", runs_server);
+        if !redirect_uri.is_empty() && Url::parse(&redirect_uri).is_err() {
+            settings.error(format!("Invalid DISCORD_REDIRECT_URI {}", redirect_uri));
+        }
+        let discord = Discord {
+            client_id: settings.required_by_server("DISCORD_CLIENT_ID", runs_server),
+            client_secret: settings.required_by_server("DISCORD_CLIENT_SECRET", runs_server),
+            redirect_uri,
+            bot_token,
+        };
+
+        let openai_api_key = settings.get("OPENAI_API_KEY");
+        if runs_bot && features.llm_classifier && openai_api_key.is_none() {
+            settings.error(
+                "OPENAI_API_KEY is required by the LLM classifier, turn it off with FEATURE_LLM_CLASSIFIER=false",
+            );
+        }
+
+        let admin_ids = settings.list("ADMIN_IDS").unwrap_or_default();
+
+        let quota_timezone = match settings.get("QUOTA_TIMEZONE") {
+            Some(name) => time::parse_timezone(&name).unwrap_or_else(|| {
+                settings.error(format!("Invalid QUOTA_TIMEZONE {}", name));
+                time::DEFAULT_QUOTA_TIMEZONE
+            }),
+            None => time::DEFAULT_QUOTA_TIMEZONE,
+        };
+        let quota_policy = match settings.get("QUOTA_POLICY") {
+            Some(name) => QuotaPolicy::parse(&name).unwrap_or_else(|| {
+                settings.error(format!(
+                    "Invalid QUOTA_POLICY {}, expected daily, rolling, weekly or burst",
+                    name
+                ));
+                QuotaPolicy::Daily
+            }),
+            None => QuotaPolicy::Daily,
+        };
+        let default_cost = QuestionCost::default();
+        let question_cost = QuestionCost {
+            public: settings
+                .parse("QUESTION_COST_PUBLIC")
+                .unwrap_or(default_cost.public),
+            private: settings
+                .parse("QUESTION_COST_PRIVATE")
+                .unwrap_or(default_cost.private),
+            long: settings
+                .parse("QUESTION_COST_LONG")
+                .unwrap_or(default_cost.long),
+            long_body: settings.parse("QUESTION_LONG_BODY"),
+        };
+
+        let notifiers = settings.notifiers(&features, discord.bot_token.as_deref(), &admin_ids);
+
+        if !settings.errors.is_empty() {
+            return Err(Error::Config(format!(
+                "Invalid configuration:\n  - {}",
+                settings.errors.join("\n  - ")
+            )));
+        }
+
+        if admin_ids.is_empty() {
+            warn!("ADMIN_IDS is empty, nobody will be able to answer questions");
+        }
+        if notifiers.is_empty() {
+            warn!("No notifiers configured, askees will not be notified of new questions");
+        }
+
+        Ok(Self {
+            mode,
+            port,
+            database,
+            encryption_key,
+            discord,
+            openai_api_key,
+            admin_ids,
+            quota_timezone,
+            quota_policy,
+            question_cost,
+            notifiers,
+            features,
+        })
+    }
+}
+
+fn read_file(path: &str) -> Result<toml::Table> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("Failed to read config file {}: {}", path, e)))?;
+    contents
+        .parse()
+        .map_err(|e| Error::Config(format!("Invalid config file {}: {}", path, e)))
+}
+
+/// The env layered over the config file, collecting errors instead of stopping at the first
+struct Settings {
+    env: HashMap<String, String>,
+    file: toml::Table,
+    errors: Vec<String>,
+}
+
+impl Settings {
+    fn error(&mut self, message: impl Into<String>) {
+        self.errors.push(message.into());
+    }
+
+    /// Catches typos in the config file, which would otherwise be silently ignored
+    fn check_file_keys(&mut self) {
+        let mut unknown = Vec::new();
+        for (key, value) in &self.file {
+            match value.as_table() {
+                Some(table) => unknown.extend(
+                    table
+                        .keys()
+                        .map(|inner| format!("{}.{}", key, inner))
+                        .filter(|path| !is_setting(path)),
+                ),
+                None if !is_setting(key) => unknown.push(key.clone()),
+                None => {}
+            }
+        }
+        for path in unknown {
+            self.error(format!("Unknown setting `{}` in the config file", path));
+        }
+    }
+
+    /// The env var if set and not empty, else its key in the config file
+    fn get(&mut self, var: &str) -> Option<String> {
+        if let Some(value) = self.env.get(var).filter(|value| !value.trim().is_empty()) {
+            return Some(value.clone());
+        }
+
+        let path = file_path(var);
+        let value = match path.split_once('.') {
+            Some((table, key)) => self.file.get(table)?.get(key)?,
+            None => self.file.get(path)?,
+        };
+        let value = file_value(value);
+        if value.is_none() {
+            self.error(format!(
+                "`{}` in the config file must be a string, number, boolean or list",
+                path
+            ));
+        }
+        value
+    }
+
+    fn required(&mut self, var: &str) -> String {
+        self.get(var).unwrap_or_else(|| {
+            self.error(format!(
+                "{} is not set (or `{}` in the config file)",
+                var,
+                file_path(var)
+            ));
+            String::new()
+        })
+    }
+
+    /// Only the web app uses it, a bot-only process goes without
+    fn required_by_server(&mut self, var: &str, runs_server: bool) -> String {
+        if runs_server {
+            self.required(var)
+        } else {
+            self.get(var).unwrap_or_default()
+        }
+    }
+
+    fn parse<T: FromStr>(&mut self, var: &str) -> Option<T> {
+        let value = self.get(var)?;
+        let parsed = value.trim().parse().ok();
+        if parsed.is_none() {
+            self.error(format!("Invalid {} {}", var, value));
+        }
+        parsed
+    }
+
+    /// Feature toggles default to on
+    fn flag(&mut self, var: &str) -> bool {
+        self.parse(var).unwrap_or(true)
+    }
+
+    /// Comma separated in the env, a list or a comma separated string in the file
+    fn list(&mut self, var: &str) -> Option<Vec<String>> {
+        self.get(var).map(|value| {
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+    }
+
+    /// The key is 32 bytes, written as 64 hex characters
+    fn encryption_key(&mut self, runs_server: bool) -> [u8; 32] {
+        let mut key = [0; 32];
+        if !runs_server && self.get("ENCRYPTION_KEY").is_none() {
+            OsRng.fill_bytes(&mut key);
+            return key;
+        }
+        let hex_key = self.required("ENCRYPTION_KEY");
+        if !hex_key.is_empty() && hex::decode_to_slice(hex_key.trim(), &mut key).is_err() {
+            self.error("ENCRYPTION_KEY must be 64 hex characters");
+        }
+        key
+    }
+
+    /// Defaults to the remote replica when `LIBSQL_URL` is set and to a local file otherwise
+    fn database(&mut self) -> db::Location {
+        let url = self.get("LIBSQL_URL");
+        let mode = self.get("DATABASE_MODE").unwrap_or_else(|| match url {
+            Some(_) => "remote".to_string(),
+            None => "local".to_string(),
+        });
+
+        match mode.trim() {
+            "remote" => db::Location::RemoteReplica {
+                url: self.required("LIBSQL_URL"),
+                token: self.required("LIBSQL_TOKEN"),
+            },
+            "local" => db::Location::Local(
+                self.get("DATABASE_PATH")
+                    .unwrap_or_else(|| "asklp.db".to_string()),
+            ),
+            "memory" => db::Location::Memory,
+            other => {
+                self.error(format!(
+                    "Invalid DATABASE_MODE {}, expected remote, local or memory",
+                    other
+                ));
+                db::Location::Memory
+            }
+        }
+    }
+
+    /// Without `NOTIFIERS`, Twilio is used when SMS is on and its credentials are present
+    fn notifiers(
+        &mut self,
+        features: &Features,
+        bot_token: Option<&str>,
+        admin_ids: &[String],
+    ) -> Vec<Notifier> {
+        let names = self.list("NOTIFIERS").unwrap_or_else(|| {
+            if features.sms && self.get("TWILIO_ACCOUNT_SID").is_some() {
+                vec!["twilio".to_string()]
+            } else {
+                Vec::new()
+            }
+        });
+
+        let mut notifiers = Vec::new();
+        for name in names {
+            match name.as_str() {
+                "twilio" if !features.sms => {
+                    self.error("NOTIFIERS lists twilio but SMS is turned off with FEATURE_SMS")
+                }
+                "twilio" => notifiers.push(Notifier::Twilio(Twilio {
+                    account_sid: self.required("TWILIO_ACCOUNT_SID"),
+                    auth_token: self.required("TWILIO_AUTH_TOKEN"),
+                    message_service_sid: self.required("TWILIO_MESSAGE_SERVICE_SID"),
+                    to: self.required("TWILIO_TO"),
+                    askee_id: self.askee_id("TWILIO_ASKEE_ID", admin_ids),
+                })),
+                "webhook" => {
+                    let url = self.required("WEBHOOK_URL");
+                    if !url.is_empty() && Url::parse(&url).is_err() {
+                        self.error(format!("Invalid WEBHOOK_URL {}", url));
+                    }
+                    notifiers.push(Notifier::Webhook { url });
+                }
+                "email" => {
+                    let host = self.required("SMTP_HOST");
+                    let username = self.required("SMTP_USERNAME");
+                    let password = self.required("SMTP_PASSWORD");
+                    let from = self.mailbox("SMTP_FROM");
+                    let to = self.mailbox("SMTP_TO");
+                    let askee_id = self.askee_id("SMTP_ASKEE_ID", admin_ids);
+                    if let (Some(from), Some(to)) = (from, to) {
+                        notifiers.push(Notifier::Email(Smtp {
+                            host,
+                            username,
+                            password,
+                            from,
+                            to,
+                            askee_id,
+                        }));
+                    }
+                }
+                "discord" => match bot_token {
+                    Some(_) => notifiers.push(Notifier::Discord),
+                    None => self.error("DISCORD_BOT_TOKEN is required by the discord notifier"),
+                },
+                other => self.error(format!(
+                    "Unknown notifier {} in NOTIFIERS, expected twilio, webhook, email or discord",
+                    other
+                )),
+            }
+        }
+
+        notifiers
+    }
+
+    /// The askee a single-recipient notifier is for, the first admin unless set
+    fn askee_id(&mut self, var: &str, admin_ids: &[String]) -> String {
+        if let Some(askee_id) = self.get(var) {
+            return askee_id;
+        }
+        admin_ids.first().cloned().unwrap_or_else(|| {
+            self.error(format!(
+                "{} is not set (or `{}` in the config file) and ADMIN_IDS is empty",
+                var,
+                file_path(var)
+            ));
+            String::new()
+        })
+    }
+
+    fn mailbox(&mut self, var: &str) -> Option<Mailbox> {
+        let value = self.required(var);
+        if value.is_empty() {
+            return None;
+        }
+        match value.parse() {
+            Ok(mailbox) => Some(mailbox),
+            Err(e) => {
+                self.error(format!("Invalid {} {}: {}", var, value, e));
+                None
+            }
+        }
+    }
+}
+
+fn is_setting(path: &str) -> bool {
+    SETTINGS.iter().any(|(_, setting)| *setting == path)
+}
+
+fn file_path(var: &str) -> &'static str {
+    SETTINGS
+        .iter()
+        .find(|(setting, _)| *setting == var)
+        .map(|(_, path)| *path)
+        .unwrap_or_else(|| panic!("{} is missing from SETTINGS", var))
+}
+
+/// Settings are strings in the env, so file values are turned into the same
+fn file_value(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(file_value)
+            .collect::<Option<Vec<_>>>()
+            .map(|values| values.join(",")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn minimal() -> HashMap<String, String> {
+        env(&[
+            ("ENCRYPTION_KEY", &"ab".repeat(32)),
+            ("DISCORD_CLIENT_ID", "1"),
+            ("DISCORD_CLIENT_SECRET", "secret"),
+            (
+                "DISCORD_REDIRECT_URI",
+                "http://localhost:3000/discord/callback",
+            ),
+            ("FEATURE_BOT", "false"),
+        ])
+    }
+
+    fn errors(result: Result<Config>) -> String {
+        match result {
+            Err(Error::Config(message)) => message,
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("expected the configuration to be invalid"),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::from_sources(minimal(), toml::Table::new(), None).unwrap();
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.encryption_key, [0xab; 32]);
+        assert!(matches!(config.database, db::Location::Local(ref path) if path == "asklp.db"));
+        assert_eq!(config.quota_timezone, time::DEFAULT_QUOTA_TIMEZONE);
+        assert_eq!(config.quota_policy, QuotaPolicy::Daily);
+        assert_eq!(config.question_cost, QuestionCost::default());
+        assert!(config.notifiers.is_empty());
+        assert_eq!(
+            config.features,
+            Features {
+                bot: false,
+                sms: true,
+                llm_classifier: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_file_and_env_override() {
+        let file = r#"
+            port = 8000
+            admin_ids = ["1", "2"]
+
+            [quota]
+            policy = "weekly"
+            cost_private = 2
+
+            [features]
+            sms = false
+        "#
+        .parse()
+        .unwrap();
+        let mut vars = minimal();
+        vars.insert("PORT".to_string(), "9000".to_string());
+        vars.insert("QUOTA_TIMEZONE".to_string(), "Europe/Paris".to_string());
+
+        let config = Config::from_sources(vars, file, None).unwrap();
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.admin_ids, vec!["1", "2"]);
+        assert_eq!(config.quota_policy, QuotaPolicy::Weekly);
+        assert_eq!(config.quota_timezone, chrono_tz::Europe::Paris);
+        assert_eq!(config.question_cost.private, 2);
+        assert!(!config.features.sms);
+    }
+
+    #[test]
+    fn test_reports_every_error() {
+        let file = "[discord]\nclient_idd = \"1\"".parse().unwrap();
+        let vars = env(&[
+            ("ENCRYPTION_KEY", "short"),
+            ("PORT", "eighty"),
+            ("NOTIFIERS", "twilio,pager"),
+        ]);
+
+        let message = errors(Config::from_sources(vars, file, None));
+        for expected in [
+            "Unknown setting `discord.client_idd` in the config file",
+            "Invalid PORT eighty",
+            "ENCRYPTION_KEY must be 64 hex characters",
+            "DISCORD_CLIENT_ID is not set (or `discord.client_id` in the config file)",
+            "DISCORD_BOT_TOKEN is required by the bot",
+            "OPENAI_API_KEY is required by the LLM classifier",
+            "TWILIO_AUTH_TOKEN is not set",
+            "Unknown notifier pager in NOTIFIERS",
+        ] {
+            assert!(
+                message.contains(expected),
+                "{} not in {}",
+                expected,
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_feature_toggles() {
+        let mut vars = minimal();
+        vars.insert("FEATURE_BOT".to_string(), "true".to_string());
+        vars.insert("DISCORD_BOT_TOKEN".to_string(), "token".to_string());
+        vars.insert("FEATURE_LLM_CLASSIFIER".to_string(), "false".to_string());
+        let config = Config::from_sources(vars.clone(), toml::Table::new(), None).unwrap();
+        assert!(config.features.bot);
+        assert!(config.openai_api_key.is_none());
+
+        // Twilio credentials alone turn SMS on, unless it is off
+        vars.insert("TWILIO_ACCOUNT_SID".to_string(), "sid".to_string());
+        vars.insert("TWILIO_AUTH_TOKEN".to_string(), "token".to_string());
+        vars.insert("TWILIO_MESSAGE_SERVICE_SID".to_string(), "mg".to_string());
+        vars.insert("TWILIO_TO".to_string(), "+15550000000".to_string());
+        let message = errors(Config::from_sources(vars.clone(), toml::Table::new(), None));
+        assert!(message.contains("TWILIO_ASKEE_ID is not set"));
+
+        // The phone number belongs to the first admin unless told otherwise
+        vars.insert("ADMIN_IDS".to_string(), "1,2".to_string());
+        let config = Config::from_sources(vars.clone(), toml::Table::new(), None).unwrap();
+        assert!(
+            matches!(&config.notifiers[..], [Notifier::Twilio(twilio)] if twilio.askee_id == "1")
+        );
+        vars.insert("TWILIO_ASKEE_ID".to_string(), "2".to_string());
+        let config = Config::from_sources(vars.clone(), toml::Table::new(), None).unwrap();
+        assert!(
+            matches!(&config.notifiers[..], [Notifier::Twilio(twilio)] if twilio.askee_id == "2")
+        );
+
+        vars.insert("FEATURE_SMS".to_string(), "false".to_string());
+        let config = Config::from_sources(vars.clone(), toml::Table::new(), None).unwrap();
+        assert!(config.notifiers.is_empty());
+
+        vars.insert("NOTIFIERS".to_string(), "twilio".to_string());
+        let message = errors(Config::from_sources(vars, toml::Table::new(), None));
+        assert!(message.contains("SMS is turned off"));
+    }
+
+    #[test]
+    fn test_run_modes() {
+        // The web app runs without any bot credentials
+        let mut vars = minimal();
+        vars.remove("FEATURE_BOT");
+        let config =
+            Config::from_sources(vars.clone(), toml::Table::new(), Some(Mode::Serve)).unwrap();
+        assert!(config.runs_server());
+        assert!(!config.runs_bot());
+
+        let message = errors(Config::from_sources(vars.clone(), toml::Table::new(), None));
+        assert!(message.contains("DISCORD_BOT_TOKEN is required by the bot"));
+
+        // The command line wins over RUN_MODE
+        vars.insert("RUN_MODE".to_string(), "serve".to_string());
+        vars.insert("DISCORD_BOT_TOKEN".to_string(), "token".to_string());
+        vars.insert("OPENAI_API_KEY".to_string(), "key".to_string());
+        let config = Config::from_sources(vars.clone(), toml::Table::new(), None).unwrap();
+        assert_eq!(config.mode, Mode::Serve);
+        let config =
+            Config::from_sources(vars.clone(), toml::Table::new(), Some(Mode::Bot)).unwrap();
+        assert_eq!(config.mode, Mode::Bot);
+        assert!(!config.runs_server());
+        assert!(config.runs_bot());
+
+        vars.insert("FEATURE_BOT".to_string(), "false".to_string());
+        let message = errors(Config::from_sources(
+            vars,
+            toml::Table::new(),
+            Some(Mode::Bot),
+        ));
+        assert!(message.contains("the bot is turned off"));
+    }
+
+    #[test]
+    fn test_bot_mode_needs_no_web_settings() {
+        let vars = env(&[
+            ("DISCORD_BOT_TOKEN", "token"),
+            ("FEATURE_LLM_CLASSIFIER", "false"),
+        ]);
+        let config =
+            Config::from_sources(vars.clone(), toml::Table::new(), Some(Mode::Bot)).unwrap();
+        assert!(config.runs_bot());
+        assert!(config.discord.client_id.is_empty());
+        assert!(crate::auth::oauth_client(&config.discord).is_ok());
+
+        let message = errors(Config::from_sources(
+            vars,
+            toml::Table::new(),
+            Some(Mode::Serve),
+        ));
+        assert!(message.contains("ENCRYPTION_KEY is not set"));
+        assert!(message.contains("DISCORD_CLIENT_ID is not set"));
+    }
+}
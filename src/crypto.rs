@@ -2,16 +2,11 @@ use aes_gcm::{
     aead::{rand_core::RngCore, Aead, KeyInit, OsRng},
     Aes256Gcm, Nonce,
 };
+use sha2::{Digest, Sha256};
 
 const NONCE_SIZE: usize = 12;
-
-pub fn get_key() -> [u8; 32] {
-    let key = std::env::var("ENCRYPTION_KEY").expect("ENCRYPTION_KEY not set");
-    assert!(key.len() == 64, "ENCRYPTION_KEY must be 64 characters long");
-    let mut key_bytes = [0; 32];
-    hex::decode_to_slice(key, &mut key_bytes).expect("invalid ENCRYPTION_KEY");
-    key_bytes
-}
+const TOKEN_SIZE: usize = 32;
+pub const TOKEN_PREFIX: &str = "asklp_";
 
 pub fn encrypt(key: &[u8; 32], plaintext: &str) -> (String, String) {
     let cipher = Aes256Gcm::new(key.into());
@@ -40,6 +35,18 @@ pub fn decrypt(key: &[u8; 32], ciphertext_hex: &str, nonce_hex: &str) -> String
     String::from_utf8(plaintext_bytes).expect("invalid UTF-8")
 }
 
+/// Generates a random personal API token
+pub fn generate_token() -> String {
+    let mut token_bytes = [0; TOKEN_SIZE];
+    OsRng.fill_bytes(&mut token_bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(token_bytes))
+}
+
+/// Hash stored in place of an API token. Tokens are long and random so a plain SHA-256 is enough.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -53,4 +60,15 @@ mod tests {
         let decrypted = decrypt(&key, &ciphertext, &nonce);
         assert_eq!(decrypted, plaintext);
     }
+
+    #[test]
+    fn test_token_hash() {
+        let token = generate_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_ne!(token, generate_token());
+
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+        assert_eq!(hash_token(&token).len(), 64);
+    }
 }
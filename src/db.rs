@@ -1,23 +1,243 @@
 use crate::handlers::app::QuestionAnswered;
-use crate::{auth::refresh_access_token, handlers::app::QuestionUser, ADMIN_ID};
-use chrono::{DateTime, NaiveDate, TimeZone, Utc};
-use chrono_tz::America::{self};
-use futures::StreamExt;
-use libsql::{de::from_row, params, Connection};
+use crate::handlers::questions::PublicQuestionSearch;
+use crate::migrations;
+use crate::{auth::refresh_access_token, handlers::app::QuestionUser};
+use chrono::{DateTime, NaiveDate, Utc};
+use libsql::{de::from_row, params, Builder, Connection, Rows};
 use oauth2::basic::BasicClient;
-use serde::{Deserialize, Serialize};
-use tracing::error;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tracing::{error, info};
+
+/// SQLite's primary result code for constraint violations
+const SQLITE_CONSTRAINT: i32 = 19;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The database could not be reached or failed to run the statement
+    Connection(libsql::Error),
+    /// A write broke a UNIQUE, FOREIGN KEY, CHECK or NOT NULL constraint
+    Constraint(String),
+    /// A row could not be decoded into the expected type
+    Decode(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Connection(e) => write!(f, "database error: {}", e),
+            Error::Constraint(e) => write!(f, "constraint violation: {}", e),
+            Error::Decode(e) => write!(f, "failed to decode row: {}", e),
+        }
+    }
+}
+
+impl From<libsql::Error> for Error {
+    fn from(e: libsql::Error) -> Self {
+        match e {
+            libsql::Error::SqliteFailure(code, msg) if code & 0xff == SQLITE_CONSTRAINT => {
+                Error::Constraint(msg)
+            }
+            libsql::Error::RemoteSqliteFailure(code, _, msg) if code == SQLITE_CONSTRAINT => {
+                Error::Constraint(msg)
+            }
+            // Writes delegated to the primary only surface the error as text
+            e if e.to_string().contains("SQLITE_CONSTRAINT") => Error::Constraint(e.to_string()),
+            e => Error::Connection(e),
+        }
+    }
+}
+
+impl From<serde::de::value::Error> for Error {
+    fn from(e: serde::de::value::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+/// Decodes every remaining row, stopping at the first failure
+async fn collect_rows<T: DeserializeOwned>(mut rows: Rows) -> Result<Vec<T>, Error> {
+    let mut items = Vec::new();
+    while let Some(row) = rows.next().await? {
+        items.push(from_row::<T>(&row)?);
+    }
+    Ok(items)
+}
+
+/// Where the database lives, picked with `DATABASE_MODE`
+#[derive(Debug, Clone)]
+pub enum Location {
+    /// Embedded replica of a remote libsql database (`LIBSQL_URL`, `LIBSQL_TOKEN`)
+    RemoteReplica { url: String, token: String },
+    /// Plain SQLite file at `DATABASE_PATH`, for offline development
+    Local(String),
+    /// Throwaway in-memory database, for tests
+    Memory,
+}
+
+#[derive(Clone)]
+pub struct Database {
+    inner: Arc<libsql::Database>,
+    replica: bool,
+    /// When the replica last pulled from the remote successfully
+    last_synced_at: Arc<RwLock<Option<DateTime<Utc>>>>,
+}
+
+impl Database {
+    pub async fn open(location: &Location) -> Result<Self, Error> {
+        let (inner, replica) = match location {
+            Location::RemoteReplica { url, token } => {
+                let db = Builder::new_remote_replica("local.db", url.clone(), token.clone())
+                    .sync_interval(Duration::from_secs(60))
+                    .build()
+                    .await?;
+                (db, true)
+            }
+            Location::Local(path) => (Builder::new_local(path).build().await?, false),
+            Location::Memory => (Builder::new_local(":memory:").build().await?, false),
+        };
+        info!("Opened {:?} database", location);
+
+        let db = Self {
+            inner: Arc::new(inner),
+            replica,
+            last_synced_at: Default::default(),
+        };
+        db.sync().await?;
+        Ok(db)
+    }
+
+    /// Pulls the latest frames from the remote, a no-op for local databases
+    pub async fn sync(&self) -> Result<(), Error> {
+        if self.replica {
+            self.inner.sync().await?;
+            *self.last_synced_at.write().unwrap() = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.replica
+    }
+
+    /// Only set for replicas
+    pub fn last_synced_at(&self) -> Option<DateTime<Utc>> {
+        *self.last_synced_at.read().unwrap()
+    }
+
+    /// Opens a connection with foreign keys enforced and the schema migrated.
+    /// Every connection to an in-memory database is its own database, so share the returned
+    /// model rather than calling this twice.
+    pub async fn model(&self) -> Result<Model, migrations::Error> {
+        let conn = self.inner.connect()?;
+        conn.execute("PRAGMA foreign_keys = ON", ()).await?;
+
+        let version = migrations::run(&conn).await?;
+        info!("Database schema at version {}", version);
+
+        Ok(Model::new(conn))
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
     pub id: String,
     pub username: String,
     pub discriminator: String,
-    pub avatar: String,
+    /// Discord's avatar hash, `None` for users on the default avatar
+    pub avatar: Option<String>,
     pub is_admin: bool,
+    pub is_askee: bool,
+    pub notify_answers: bool,
     pub joined_at: chrono::DateTime<chrono::Utc>,
-    pub daily_questions: u64,
+    /// Overrides the role limit when set, see [`Model::get_daily_limit`]
+    pub daily_limit: Option<u64>,
+    /// Day `questions_used` counts for
     pub last_question_reset: Option<NaiveDate>,
+    pub banned: bool,
+    pub bonus_questions: u64,
+    pub questions_used: u64,
+    /// IANA name, the deployment's quota timezone applies when unset
+    pub timezone: Option<String>,
+    /// See [`crate::quota::QuotaPolicy::Burst`]
+    pub quota_tat: Option<DateTime<Utc>>,
+}
+
+impl User {
+    pub fn role(&self) -> Role {
+        if self.is_admin {
+            Role::Admin
+        } else if self.is_askee {
+            Role::Askee
+        } else {
+            Role::User
+        }
+    }
+
+    /// Questions asked on `today`, older counts are stale until the next question resets them
+    pub fn questions_used_on(&self, today: NaiveDate) -> u64 {
+        match self.last_question_reset {
+            Some(day) if day == today => self.questions_used,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Askee,
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::User, Role::Askee, Role::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Askee => "askee",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoleLimit {
+    pub role: Role,
+    pub daily_limit: u64,
+}
+
+/// Raises everyone's daily limit by `extra_questions` until `ends_at`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LimitBoost {
+    pub id: String,
+    pub extra_questions: u64,
+    pub ends_at: chrono::DateTime<chrono::Utc>,
+    pub created_by: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Where the allowance for a question came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuestionAllowance {
+    Limit,
+    Bonus,
+}
+
+impl QuestionAllowance {
+    /// What the question counts against the limit. Bonus questions are paid for out of their
+    /// own balance, so they must not also count inside a rolling or weekly window.
+    pub fn counted_cost(&self, cost: u64) -> u64 {
+        match self {
+            QuestionAllowance::Limit => cost,
+            QuestionAllowance::Bonus => 0,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -29,8 +249,16 @@ pub struct Session {
     pub refresh_token: String,
     pub refresh_token_nonce: String,
     pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// Browser the user logged in with, unknown for sessions older than multi-device sessions
+    pub user_agent: Option<String>,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Updated at most every [`SESSION_SEEN_INTERVAL`]
+    pub last_seen_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+/// How stale `Session::last_seen_at` may get, so every request does not write to the database
+pub const SESSION_SEEN_INTERVAL: chrono::Duration = chrono::Duration::minutes(5);
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Question {
     pub id: String,
@@ -39,9 +267,15 @@ pub struct Question {
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub public: bool,
     pub user_id: String,
+    pub askee_id: String,
+    /// Discord channel the question was asked from, if any
+    pub channel_id: Option<String>,
+    /// Taken out of the asker's limit, see [`crate::quota::QuestionCost`]. 0 when bonus
+    /// questions paid for it.
+    pub cost: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Answer {
     pub id: String,
     pub body: String,
@@ -50,6 +284,57 @@ pub struct Answer {
     pub question_id: String,
 }
 
+/// An answer along with the title of the question it answers
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswerWithQuestion {
+    pub id: String,
+    pub body: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub question_id: String,
+    pub question_title: String,
+    pub asker_username: String,
+}
+
+/// A row of the admin user directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserSummary {
+    pub id: String,
+    pub username: String,
+    pub discriminator: String,
+    pub avatar: Option<String>,
+    pub is_admin: bool,
+    pub is_askee: bool,
+    pub banned: bool,
+    pub joined_at: chrono::DateTime<chrono::Utc>,
+    pub daily_limit: Option<u64>,
+    pub bonus_questions: u64,
+    /// Questions the user asked
+    pub questions_asked: u64,
+    /// Questions sent to the user as an askee
+    pub questions_received: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UserSort {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    /// Space separated, see [`crate::auth::Scopes`]
+    pub scopes: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
 #[derive(Clone)]
 pub struct Model {
     conn: Connection,
@@ -60,26 +345,44 @@ impl Model {
         Self { conn }
     }
 
+    /// Runs a trivial query to check the database answers
+    pub async fn ping(&self) -> Result<(), Error> {
+        self.conn.query("SELECT 1", ()).await?;
+        Ok(())
+    }
+
+    /// The session if it is still valid, refreshing its token (and its user's profile) once it
+    /// expired
     pub async fn get_active_session(
         &self,
         oauth_client: &BasicClient,
+        http: &reqwest::Client,
+        encryption_key: &[u8; 32],
         id: &str,
-    ) -> Result<Option<Session>, libsql::Error> {
+    ) -> Result<Option<Session>, Error> {
         let param = params!(id);
         let mut rows = self
             .conn
             .query("SELECT * FROM Session WHERE id = ?", param)
             .await?;
         if let Some(row) = rows.next().await? {
-            let session: Session =
-                from_row::<Session>(&row).expect("Failed to deserialize row into Session type");
+            let session: Session = from_row::<Session>(&row)?;
 
             if session.expires_at > chrono::Utc::now() {
                 Ok(Some(session))
             } else {
-                match refresh_access_token(oauth_client, &session).await {
-                    Ok(new_session) => {
+                match refresh_access_token(oauth_client, http, encryption_key, &session).await {
+                    Ok((new_session, profile)) => {
                         self.update_session(&new_session).await?;
+                        if let Some(profile) = profile {
+                            self.update_profile(
+                                &session.user_id,
+                                &profile.username,
+                                &profile.discriminator,
+                                profile.avatar.as_deref(),
+                            )
+                            .await?;
+                        }
                         Ok(Some(new_session))
                     }
                     Err(e) => {
@@ -96,15 +399,14 @@ impl Model {
     pub async fn get_active_session_by_user_id(
         &self,
         user_id: &str,
-    ) -> Result<Option<Session>, libsql::Error> {
+    ) -> Result<Option<Session>, Error> {
         let param = params!(user_id);
         let mut rows = self
             .conn
             .query("SELECT * FROM Session WHERE user_id = ?", param)
             .await?;
         while let Some(row) = rows.next().await? {
-            let session: Session =
-                from_row::<Session>(&row).expect("Failed to deserialize row into Session type");
+            let session: Session = from_row::<Session>(&row)?;
             if session.expires_at > chrono::Utc::now() {
                 // Active session found
                 return Ok(Some(session));
@@ -115,17 +417,16 @@ impl Model {
         }
         Ok(None)
     }
-    pub async fn get_session(&self, id: &str) -> Result<Option<Session>, libsql::Error> {
+    pub async fn get_session(&self, id: &str) -> Result<Option<Session>, Error> {
         let param = params!(id);
         let mut rows = self
             .conn
             .query("SELECT * FROM Session WHERE id = ?", param)
             .await?;
         let first = rows.next().await?;
-        Ok(first
-            .map(|r| from_row::<Session>(&r).expect("Failed to deserialize row into Session type")))
+        Ok(first.map(|r| from_row::<Session>(&r)).transpose()?)
     }
-    pub async fn create_user(&self, user: User) -> Result<(), libsql::Error> {
+    pub async fn create_user(&self, user: User) -> Result<(), Error> {
         let last_question_reset = user.last_question_reset.map(|d| d.to_string());
         let params = params!(
             user.id,
@@ -133,278 +434,1630 @@ impl Model {
             user.discriminator,
             user.avatar,
             user.is_admin,
+            user.is_askee,
+            user.notify_answers,
             user.joined_at.to_rfc3339(),
-            user.daily_questions,
-            last_question_reset
+            user.daily_limit
+                .map(|limit| i64::try_from(limit).unwrap_or(i64::MAX)),
+            last_question_reset,
+            user.banned,
+            user.bonus_questions,
+            user.questions_used,
+            user.timezone
         );
         self.conn
             .execute(
-                "INSERT INTO User (id, username, discriminator, avatar, is_admin, joined_at, daily_questions, last_question_reset) VALUES (?,?,?,?,?,?,?,?)",
+                "INSERT INTO User (id, username, discriminator, avatar, is_admin, is_askee, notify_answers, joined_at, daily_limit, last_question_reset, banned, bonus_questions, questions_used, timezone) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?)",
                 params,
             )
             .await?;
         Ok(())
     }
 
-    pub async fn get_user(&self, id: &str) -> Result<Option<User>, libsql::Error> {
+    pub async fn get_user(&self, id: &str) -> Result<Option<User>, Error> {
         let param = params!(id);
         let mut rows = self
             .conn
             .query("SELECT * FROM User WHERE id = ?", param)
             .await?;
         let first = rows.next().await?;
-        Ok(first.map(|r| from_row::<User>(&r).expect("Failed to deserialize row into User type")))
+        Ok(first.map(|r| from_row::<User>(&r)).transpose()?)
     }
 
-    pub async fn get_users(&self) -> Result<Vec<User>, libsql::Error> {
-        let res = self.conn.query("SELECT * FROM User", params![]).await?;
-        let stream = res.into_stream();
+    /// Keeps the user's Discord name and avatar in sync with Discord
+    pub async fn update_profile(
+        &self,
+        id: &str,
+        username: &str,
+        discriminator: &str,
+        avatar: Option<&str>,
+    ) -> Result<(), Error> {
+        self.conn
+            .execute(
+                "UPDATE User SET username = ?, discriminator = ?, avatar = ? WHERE id = ?",
+                params!(username, discriminator, avatar, id),
+            )
+            .await?;
+        Ok(())
+    }
 
-        let users = stream
-            .map(|row| from_row::<User>(&row.unwrap()).unwrap())
-            .collect::<Vec<_>>()
-            .await;
-        Ok(users)
+    pub async fn get_users(&self) -> Result<Vec<User>, Error> {
+        let res = self.conn.query("SELECT * FROM User", params![]).await?;
+        collect_rows(res).await
     }
 
-    pub async fn create_session(&self, session: Session) -> Result<(), libsql::Error> {
-        let params = params!(
-            session.id,
-            session.user_id,
-            session.access_token,
-            session.access_token_nonce,
-            session.refresh_token,
-            session.refresh_token_nonce,
-            session.expires_at.to_rfc3339(),
+    /// Users whose name contains `search` (or whose id is `search`), with their question counts
+    pub async fn search_users(
+        &self,
+        search: Option<&str>,
+        sort: UserSort,
+    ) -> Result<Vec<UserSummary>, Error> {
+        let order = match sort {
+            UserSort::Newest => "DESC",
+            UserSort::Oldest => "ASC",
+        };
+        let query = format!(
+            "SELECT
+                User.id,
+                User.username,
+                User.discriminator,
+                User.avatar,
+                User.is_admin,
+                User.is_askee,
+                User.banned,
+                User.joined_at,
+                User.daily_limit,
+                User.bonus_questions,
+                (SELECT COUNT(*) FROM Question WHERE Question.user_id = User.id) as questions_asked,
+                (SELECT COUNT(*) FROM Question WHERE Question.askee_id = User.id) as questions_received
+            FROM User
+            WHERE ?1 IS NULL OR User.id = ?1 OR User.username LIKE '%' || ?2 || '%' ESCAPE '\\'
+            ORDER BY User.joined_at {}",
+            order
         );
-        self.conn
-            .execute(
-                "INSERT INTO Session (id, user_id, access_token, access_token_nonce, refresh_token, refresh_token_nonce, expires_at) VALUES (?,?,?,?,?,?,?)",
-                params,
+        // Wildcards in the search are matched as typed
+        let pattern = search.map(|search| {
+            search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        });
+        let res = self.conn.query(&query, params!(search, pattern)).await?;
+        collect_rows(res).await
+    }
+
+    pub async fn get_askees(&self) -> Result<Vec<User>, Error> {
+        let res = self
+            .conn
+            .query(
+                "SELECT * FROM User WHERE is_askee = 1 ORDER BY username",
+                params![],
             )
             .await?;
-        Ok(())
+        collect_rows(res).await
     }
 
-    pub async fn delete_session(&self, id: &str) -> Result<(), libsql::Error> {
-        let param = params!(id);
+    pub async fn set_askee(&self, user_id: &str, is_askee: bool) -> Result<(), Error> {
+        let params = params!(is_askee, user_id);
         self.conn
-            .execute("DELETE FROM Session WHERE id = ?", param)
+            .execute("UPDATE User SET is_askee = ? WHERE id = ?", params)
             .await?;
         Ok(())
     }
 
-    pub async fn delete_sessions_by_user_id(&self, user_id: &str) -> Result<(), libsql::Error> {
-        let param = params!(user_id);
+    pub async fn set_banned(&self, user_id: &str, banned: bool) -> Result<(), Error> {
+        let params = params!(banned, user_id);
         self.conn
-            .execute("DELETE FROM Session WHERE user_id = ?", param)
+            .execute("UPDATE User SET banned = ? WHERE id = ?", params)
             .await?;
         Ok(())
     }
 
-    pub async fn get_questions_by_user_id(
+    pub async fn set_daily_limit(
         &self,
         user_id: &str,
-    ) -> Result<Vec<QuestionAnswered>, libsql::Error> {
-        let param = params!(user_id);
-        let res = self
-            .conn
-            .query(
-                "SELECT
-                    Question.*,
-                    EXISTS (
-                        SELECT 1
-                        FROM Answer
-                        WHERE Answer.question_id = Question.id
-                    ) as answered,
-                    Answer.body as answer_body
-                FROM Question
-                LEFT JOIN Answer ON Answer.question_id = Question.id
-                WHERE Question.user_id = ?
-                ORDER BY created_at DESC
-                ",
-                param,
-            )
+        daily_limit: Option<u64>,
+    ) -> Result<(), Error> {
+        let daily_limit = daily_limit.map(|limit| i64::try_from(limit).unwrap_or(i64::MAX));
+        let params = params!(daily_limit, user_id);
+        self.conn
+            .execute("UPDATE User SET daily_limit = ? WHERE id = ?", params)
             .await?;
-        let stream = res.into_stream();
-
-        let questions = stream
-            .map(|row| from_row::<QuestionAnswered>(&row.unwrap()).unwrap())
-            .collect::<Vec<_>>()
-            .await;
-        Ok(questions)
+        Ok(())
     }
 
-    pub async fn create_question(&self, question: Question) -> Result<(), libsql::Error> {
-        let params = params!(
-            question.id,
-            question.title,
-            question.body,
-            question.public,
-            question.created_at.to_rfc3339(),
-            question.user_id
-        );
+    pub async fn add_bonus_questions(&self, user_id: &str, bonus: u64) -> Result<(), Error> {
+        let params = params!(bonus, user_id);
         self.conn
             .execute(
-                "INSERT INTO Question (id, title, body, public, created_at, user_id) VALUES (?,?,?,?,?,?)",
+                "UPDATE User SET bonus_questions = bonus_questions + ? WHERE id = ?",
                 params,
             )
             .await?;
         Ok(())
     }
-    pub async fn get_question(&self, id: &str) -> Result<Option<Question>, libsql::Error> {
-        let param = params!(id);
-        let mut rows = self
-            .conn
-            .query("SELECT * FROM Question WHERE id = ?", param)
-            .await?;
-        let first = rows.next().await?;
-        Ok(first.map(|r| {
-            from_row::<Question>(&r).expect("Failed to deserialize row into Question type")
-        }))
+
+    /// Daily limit before boosts: the user's own override, then their role's, then the default
+    pub async fn get_daily_limit(&self, user: &User) -> Result<u64, Error> {
+        if let Some(limit) = user.daily_limit {
+            return Ok(limit);
+        }
+        let role_limit = self
+            .get_role_limits()
+            .await?
+            .into_iter()
+            .find(|limit| limit.role == user.role());
+        Ok(role_limit
+            .map(|limit| limit.daily_limit)
+            .unwrap_or(crate::GENERIC_DAILY_LIMIT))
     }
 
-    pub async fn get_unanswered_questions(&self) -> Result<Vec<QuestionUser>, libsql::Error> {
-        let param = params!(ADMIN_ID);
+    pub async fn get_role_limits(&self) -> Result<Vec<RoleLimit>, Error> {
         let res = self
             .conn
-            .query(
-                "
-                SELECT
-                    Question.id AS question_id,
-                    Question.title,
-                    Question.body,
-                    Question.created_at,
-                    Question.public,
-                    User.id AS user_id,
-                    User.avatar,
-                    User.username,
-                    EXISTS (
-                        SELECT 1
-                        FROM Answer
-                        WHERE Answer.question_id = Question.id
-                    ) as answered
+            .query("SELECT * FROM RoleLimit ORDER BY role", params![])
+            .await?;
+        collect_rows(res).await
+    }
 
-                FROM Question
-                INNER JOIN User ON User.id = Question.user_id
-                WHERE Question.user_id != ?
-                  AND NOT EXISTS (
-                    SELECT 1
-                    FROM Answer
-                    WHERE Answer.question_id = Question.id
-                )    
-                    ORDER BY Question.created_at DESC
+    /// Sets the limit for everyone with `role`, or goes back to the default with `None`
+    pub async fn set_role_limit(&self, role: Role, daily_limit: Option<u64>) -> Result<(), Error> {
+        match daily_limit {
+            Some(limit) => {
+                self.conn
+                    .execute(
+                        "INSERT INTO RoleLimit (role, daily_limit) VALUES (?1, ?2)
+                        ON CONFLICT (role) DO UPDATE SET daily_limit = ?2",
+                        params!(role.as_str(), limit),
+                    )
+                    .await?;
+            }
+            None => {
+                self.conn
+                    .execute(
+                        "DELETE FROM RoleLimit WHERE role = ?",
+                        params!(role.as_str()),
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
 
-                  ",
-                param,
+    pub async fn create_limit_boost(&self, boost: LimitBoost) -> Result<(), Error> {
+        let params = params!(
+            boost.id,
+            boost.extra_questions,
+            boost.ends_at.to_rfc3339(),
+            boost.created_by,
+            boost.created_at.to_rfc3339(),
+        );
+        self.conn
+            .execute(
+                "INSERT INTO LimitBoost (id, extra_questions, ends_at, created_by, created_at) VALUES (?,?,?,?,?)",
+                params,
             )
             .await?;
-        let stream = res.into_stream();
-
-        let questions = stream
-            .map(|row| from_row::<QuestionUser>(&row.unwrap()).unwrap())
-            .collect::<Vec<_>>()
-            .await;
-        Ok(questions)
+        Ok(())
     }
 
-    pub async fn get_user_daily_questions(
+    pub async fn get_active_limit_boosts(
         &self,
-        user_id: &str,
-    ) -> Result<Vec<Question>, libsql::Error> {
-        let now_utc: DateTime<Utc> = Utc::now();
-        let now_eastern = now_utc.with_timezone(&America::New_York);
-
-        let today_eastern = now_eastern.date_naive();
-        let start_of_day_eastern = today_eastern
-            .and_hms_opt(0, 0, 0)
-            .expect("Failed to get start of day");
-        let end_of_day_eastern = today_eastern
-            .and_hms_opt(23, 59, 59)
-            .expect("Failed to get end of day");
-
-        let start_of_day_eastern_dt = America::New_York
-            .from_local_datetime(&start_of_day_eastern)
-            .unwrap();
-        let end_of_day_eastern_dt = America::New_York
-            .from_local_datetime(&end_of_day_eastern)
-            .unwrap();
-
-        let start_of_day_utc = start_of_day_eastern_dt.with_timezone(&Utc);
-        let end_of_day_utc = end_of_day_eastern_dt.with_timezone(&Utc);
-
-        let start_utc = start_of_day_utc.to_rfc3339();
-        let end_utc = end_of_day_utc.to_rfc3339();
-
-        let param = params!(user_id, start_utc, end_utc);
+        now: DateTime<Utc>,
+    ) -> Result<Vec<LimitBoost>, Error> {
         let res = self
             .conn
             .query(
-                "SELECT * FROM Question WHERE user_id = ? AND created_at BETWEEN ? AND ?",
-                param,
+                "SELECT * FROM LimitBoost WHERE ends_at > ? ORDER BY ends_at",
+                params!(now.to_rfc3339()),
             )
             .await?;
-        let stream = res.into_stream();
-
-        let questions = stream
-            .map(|row| from_row::<Question>(&row.unwrap()).unwrap())
-            .collect::<Vec<_>>()
-            .await;
-        Ok(questions)
+        collect_rows(res).await
     }
 
-    pub async fn clean_up_expired_sessions(&self) -> Result<u64, libsql::Error> {
-        let now = chrono::Utc::now().to_rfc3339();
-        let affected = self
+    /// Ends a boost early. False when there is no such boost running.
+    pub async fn end_limit_boost(&self, id: &str, now: DateTime<Utc>) -> Result<bool, Error> {
+        let ended = self
             .conn
-            .execute("DELETE FROM Session WHERE expires_at <= ?", params!(now))
+            .execute(
+                "UPDATE LimitBoost SET ends_at = ? WHERE id = ? AND ends_at > ?",
+                params!(now.to_rfc3339(), id, now.to_rfc3339()),
+            )
             .await?;
-        Ok(affected)
+        Ok(ended > 0)
     }
 
-    pub async fn update_session(&self, new_session: &Session) -> Result<(), libsql::Error> {
-        let params = params!(
-            new_session.access_token.clone(),
-            new_session.refresh_token.clone(),
-            new_session.expires_at.to_rfc3339(),
-            new_session.id.clone(),
-        );
-        self.conn
-        .execute(
-            "UPDATE Session SET access_token = ?, refresh_token = ?, expires_at = ? WHERE id = ?",
-            params,
-        )
-        .await?;
-        Ok(())
+    /// Spends `cost` from today's allowance if it fits in `limit`. The counter starts over the
+    /// first time the user asks on a new `today`.
+    pub async fn use_daily_questions(
+        &self,
+        user_id: &str,
+        today: NaiveDate,
+        limit: u64,
+        cost: u64,
+    ) -> Result<bool, Error> {
+        let used = self
+            .conn
+            .execute(
+                "UPDATE User SET
+                    questions_used = CASE WHEN last_question_reset = ?1 THEN questions_used ELSE 0 END + ?4,
+                    last_question_reset = ?1
+                WHERE id = ?2
+                AND CASE WHEN last_question_reset = ?1 THEN questions_used ELSE 0 END + ?4 <= ?3",
+                params!(today.to_string(), user_id, limit, cost),
+            )
+            .await?;
+        Ok(used > 0)
     }
 
-    pub async fn create_answer(&self, answer: Answer) -> Result<(), libsql::Error> {
-        let params = params!(
-            answer.id,
-            answer.body,
-            answer.created_at.to_rfc3339(),
-            answer.user_id,
-            answer.question_id
-        );
-        self.conn
+    /// Claims the run of a shared job for this process. False when another process claimed it
+    /// less than `lease` ago.
+    pub async fn claim_job_run(
+        &self,
+        name: &str,
+        now: DateTime<Utc>,
+        lease: chrono::Duration,
+    ) -> Result<bool, Error> {
+        let claimed = self
+            .conn
             .execute(
-                "INSERT INTO Answer (id, body, created_at, user_id, question_id) VALUES (?,?,?,?,?)",
-                params,
+                "INSERT INTO JobRun (name, claimed_at) VALUES (?1, ?2)
+                ON CONFLICT (name) DO UPDATE SET claimed_at = ?2
+                WHERE JobRun.claimed_at <= ?3",
+                params!(name, now.to_rfc3339(), (now - lease).to_rfc3339()),
             )
             .await?;
-        Ok(())
+        Ok(claimed > 0)
     }
-    pub async fn get_question_answer(
+
+    /// Gives back `cost` spent from the `today` counter
+    pub async fn refund_daily_questions(
         &self,
-        question_id: &str,
-    ) -> Result<Option<Answer>, libsql::Error> {
-        let param = params!(question_id);
+        user_id: &str,
+        today: NaiveDate,
+        cost: u64,
+    ) -> Result<(), Error> {
+        self.conn
+            .execute(
+                "UPDATE User SET questions_used = MAX(questions_used - ?3, 0)
+                WHERE id = ?1 AND last_question_reset = ?2",
+                params!(user_id, today.to_string(), cost),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Users with a daily counter that may be left over from a previous day
+    pub async fn get_users_with_questions_used(&self) -> Result<Vec<User>, Error> {
+        let res = self
+            .conn
+            .query("SELECT * FROM User WHERE questions_used > 0", params![])
+            .await?;
+        collect_rows(res).await
+    }
+
+    /// Zeroes the daily counter unless it counts `today`. Only tidies up, a stale counter is
+    /// already ignored by [`User::questions_used_on`].
+    pub async fn reset_questions_used(
+        &self,
+        user_id: &str,
+        today: NaiveDate,
+    ) -> Result<bool, Error> {
+        let reset = self
+            .conn
+            .execute(
+                "UPDATE User SET questions_used = 0
+                WHERE id = ?1 AND questions_used > 0 AND last_question_reset IS NOT ?2",
+                params!(user_id, today.to_string()),
+            )
+            .await?;
+        Ok(reset > 0)
+    }
+
+    pub async fn use_bonus_questions(&self, user_id: &str, cost: u64) -> Result<bool, Error> {
+        let used = self
+            .conn
+            .execute(
+                "UPDATE User SET bonus_questions = bonus_questions - ?1
+                WHERE id = ?2 AND bonus_questions >= ?1",
+                params!(cost, user_id),
+            )
+            .await?;
+        Ok(used > 0)
+    }
+
+    /// Moves the burst policy state from `current` to `tat`. False when another question got
+    /// there first.
+    pub async fn use_burst(
+        &self,
+        user_id: &str,
+        current: Option<DateTime<Utc>>,
+        tat: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let used = self
+            .conn
+            .execute(
+                "UPDATE User SET quota_tat = ?1 WHERE id = ?2 AND quota_tat IS ?3",
+                params!(
+                    tat.to_rfc3339(),
+                    user_id,
+                    current.map(|tat| tat.to_rfc3339())
+                ),
+            )
+            .await?;
+        Ok(used > 0)
+    }
+
+    /// When the user asked since `since` and what each question cost against the limit, oldest
+    /// first
+    pub async fn get_question_costs_since(
+        &self,
+        user_id: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, u64)>, Error> {
+        #[derive(Deserialize)]
+        struct Cost {
+            created_at: DateTime<Utc>,
+            cost: u64,
+        }
+
+        let res = self
+            .conn
+            .query(
+                "SELECT created_at, cost FROM Question
+                WHERE user_id = ? AND created_at >= ? AND cost > 0
+                ORDER BY created_at",
+                params!(user_id, since.to_rfc3339()),
+            )
+            .await?;
+        Ok(collect_rows::<Cost>(res)
+            .await?
+            .into_iter()
+            .map(|row| (row.created_at, row.cost))
+            .collect())
+    }
+
+    /// Changes the user's timezone. A count kept for `old_today` moves over to `new_today`, the
+    /// same moment in the new timezone, so switching does not hand out a fresh quota.
+    pub async fn set_timezone(
+        &self,
+        user_id: &str,
+        timezone: Option<&str>,
+        old_today: NaiveDate,
+        new_today: NaiveDate,
+    ) -> Result<(), Error> {
+        let params = params!(
+            timezone,
+            old_today.to_string(),
+            new_today.to_string(),
+            user_id
+        );
+        self.conn
+            .execute(
+                "UPDATE User SET
+                    timezone = ?1,
+                    last_question_reset = CASE WHEN last_question_reset = ?2 THEN ?3 ELSE last_question_reset END
+                WHERE id = ?4",
+                params,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_notify_answers(
+        &self,
+        user_id: &str,
+        notify_answers: bool,
+    ) -> Result<(), Error> {
+        let params = params!(notify_answers, user_id);
+        self.conn
+            .execute("UPDATE User SET notify_answers = ? WHERE id = ?", params)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn create_session(&self, session: Session) -> Result<(), Error> {
+        let params = params!(
+            session.id,
+            session.user_id,
+            session.access_token,
+            session.access_token_nonce,
+            session.refresh_token,
+            session.refresh_token_nonce,
+            session.expires_at.to_rfc3339(),
+            session.user_agent,
+            session.created_at.map(|at| at.to_rfc3339()),
+            session.last_seen_at.map(|at| at.to_rfc3339()),
+        );
+        self.conn
+            .execute(
+                "INSERT INTO Session (id, user_id, access_token, access_token_nonce, refresh_token, refresh_token_nonce, expires_at, user_agent, created_at, last_seen_at) VALUES (?,?,?,?,?,?,?,?,?,?)",
+                params,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_session(&self, id: &str) -> Result<(), Error> {
+        let param = params!(id);
+        self.conn
+            .execute("DELETE FROM Session WHERE id = ?", param)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_sessions_by_user_id(&self, user_id: &str) -> Result<(), Error> {
+        let param = params!(user_id);
+        self.conn
+            .execute("DELETE FROM Session WHERE user_id = ?", param)
+            .await?;
+        Ok(())
+    }
+
+    /// Signs the user out everywhere but the session `keep_id`
+    pub async fn delete_other_sessions(&self, user_id: &str, keep_id: &str) -> Result<u64, Error> {
+        let deleted = self
+            .conn
+            .execute(
+                "DELETE FROM Session WHERE user_id = ? AND id != ?",
+                params!(user_id, keep_id),
+            )
+            .await?;
+        Ok(deleted)
+    }
+
+    /// The user's sessions, most recently used first
+    pub async fn get_sessions_by_user_id(&self, user_id: &str) -> Result<Vec<Session>, Error> {
+        let res = self
+            .conn
+            .query(
+                "SELECT * FROM Session WHERE user_id = ?
+                ORDER BY COALESCE(last_seen_at, created_at) DESC",
+                params!(user_id),
+            )
+            .await?;
+        collect_rows(res).await
+    }
+
+    /// Records that the session was used at `now`, unless it already was recently
+    pub async fn touch_session(&self, id: &str, now: DateTime<Utc>) -> Result<(), Error> {
+        let stale = now - SESSION_SEEN_INTERVAL;
+        self.conn
+            .execute(
+                "UPDATE Session SET last_seen_at = ?2
+                WHERE id = ?1 AND (last_seen_at IS NULL OR last_seen_at < ?3)",
+                params!(id, now.to_rfc3339(), stale.to_rfc3339()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_questions_by_user_id(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<QuestionAnswered>, Error> {
+        let param = params!(user_id);
+        let res = self
+            .conn
+            .query(
+                "SELECT
+                    Question.*,
+                    Askee.username as askee_username,
+                    EXISTS (
+                        SELECT 1
+                        FROM Answer
+                        WHERE Answer.question_id = Question.id
+                    ) as answered,
+                    (
+                        SELECT Answer.body
+                        FROM Answer
+                        WHERE Answer.question_id = Question.id
+                        ORDER BY Answer.created_at
+                        LIMIT 1
+                    ) as answer_body
+                FROM Question
+                INNER JOIN User AS Askee ON Askee.id = Question.askee_id
+                WHERE Question.user_id = ?
+                ORDER BY created_at DESC
+                ",
+                param,
+            )
+            .await?;
+        collect_rows(res).await
+    }
+
+    pub async fn create_question(&self, question: Question) -> Result<(), Error> {
+        let params = params!(
+            question.id,
+            question.title,
+            question.body,
+            question.public,
+            question.created_at.to_rfc3339(),
+            question.user_id,
+            question.askee_id,
+            question.channel_id,
+            question.cost
+        );
+        self.conn
+            .execute(
+                "INSERT INTO Question (id, title, body, public, created_at, user_id, askee_id, channel_id, cost) VALUES (?,?,?,?,?,?,?,?,?)",
+                params,
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn get_question(&self, id: &str) -> Result<Option<Question>, Error> {
+        let param = params!(id);
+        let mut rows = self
+            .conn
+            .query("SELECT * FROM Question WHERE id = ?", param)
+            .await?;
+        let first = rows.next().await?;
+        Ok(first.map(|r| from_row::<Question>(&r)).transpose()?)
+    }
+
+    pub async fn get_unanswered_questions(
+        &self,
+        askee_id: &str,
+    ) -> Result<Vec<QuestionUser>, Error> {
+        let param = params!(askee_id);
+        let res = self
+            .conn
+            .query(
+                "
+                SELECT
+                    Question.id AS question_id,
+                    Question.title,
+                    Question.body,
+                    Question.created_at,
+                    Question.public,
+                    User.id AS user_id,
+                    User.avatar,
+                    User.username,
+                    Askee.username AS askee_username,
+                    EXISTS (
+                        SELECT 1
+                        FROM Answer
+                        WHERE Answer.question_id = Question.id
+                    ) as answered
+
+                FROM Question
+                INNER JOIN User ON User.id = Question.user_id
+                INNER JOIN User AS Askee ON Askee.id = Question.askee_id
+                WHERE Question.askee_id = ?
+                  AND NOT EXISTS (
+                    SELECT 1
+                    FROM Answer
+                    WHERE Answer.question_id = Question.id
+                )    
+                    ORDER BY Question.created_at DESC
+
+                  ",
+                param,
+            )
+            .await?;
+        collect_rows(res).await
+    }
+
+    pub async fn get_public_questions(
+        &self,
+        user_id: Option<&str>,
+        answered: Option<bool>,
+    ) -> Result<Vec<PublicQuestionSearch>, Error> {
+        let param = params!(user_id, answered);
+        let res = self
+            .conn
+            .query(
+                "
+                SELECT
+                    Question.id,
+                    Question.title,
+                    Question.body,
+                    Question.created_at,
+                    User.id AS user_id,
+                    User.username,
+                    User.discriminator,
+                    User.avatar,
+                    Askee.username AS askee_username,
+                    EXISTS (
+                        SELECT 1
+                        FROM Answer
+                        WHERE Answer.question_id = Question.id
+                    ) as answered,
+                    (
+                        SELECT Answer.body
+                        FROM Answer
+                        WHERE Answer.question_id = Question.id
+                        ORDER BY Answer.created_at
+                        LIMIT 1
+                    ) as answer_body
+                FROM Question
+                INNER JOIN User ON User.id = Question.user_id
+                INNER JOIN User AS Askee ON Askee.id = Question.askee_id
+                WHERE Question.public = 1
+                  AND (?1 IS NULL OR Question.user_id = ?1)
+                  AND (?2 IS NULL OR EXISTS (
+                    SELECT 1
+                    FROM Answer
+                    WHERE Answer.question_id = Question.id
+                  ) = ?2)
+                ORDER BY Question.created_at DESC
+                ",
+                param,
+            )
+            .await?;
+        collect_rows(res).await
+    }
+
+    pub async fn clean_up_expired_sessions(&self) -> Result<u64, Error> {
+        let now = chrono::Utc::now().to_rfc3339();
+        let affected = self
+            .conn
+            .execute("DELETE FROM Session WHERE expires_at <= ?", params!(now))
+            .await?;
+        Ok(affected)
+    }
+
+    pub async fn update_session(&self, new_session: &Session) -> Result<(), Error> {
+        let params = params!(
+            new_session.access_token.clone(),
+            new_session.access_token_nonce.clone(),
+            new_session.refresh_token.clone(),
+            new_session.refresh_token_nonce.clone(),
+            new_session.expires_at.to_rfc3339(),
+            new_session.id.clone(),
+        );
+        self.conn
+        .execute(
+            "UPDATE Session SET access_token = ?, access_token_nonce = ?, refresh_token = ?, refresh_token_nonce = ?, expires_at = ? WHERE id = ?",
+            params,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn create_answer(&self, answer: Answer) -> Result<(), Error> {
+        let params = params!(
+            answer.id,
+            answer.body,
+            answer.created_at.to_rfc3339(),
+            answer.user_id,
+            answer.question_id
+        );
+        self.conn
+            .execute(
+                "INSERT INTO Answer (id, body, created_at, user_id, question_id) VALUES (?,?,?,?,?)",
+                params,
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn get_question_answer(&self, question_id: &str) -> Result<Option<Answer>, Error> {
+        let param = params!(question_id);
         let mut rows = self
             .conn
             .query("SELECT * FROM Answer WHERE question_id = ?", param)
             .await?;
         let first = rows.next().await?;
-        Ok(first
-            .map(|r| from_row::<Answer>(&r).expect("Failed to deserialize row into Answer type")))
+        Ok(first.map(|r| from_row::<Answer>(&r)).transpose()?)
+    }
+
+    /// Answers written by the askee, newest first
+    pub async fn get_answers_by_user_id(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<AnswerWithQuestion>, Error> {
+        let param = params!(user_id);
+        let res = self
+            .conn
+            .query(
+                "SELECT
+                    Answer.id,
+                    Answer.body,
+                    Answer.created_at,
+                    Answer.question_id,
+                    Question.title as question_title,
+                    Asker.username as asker_username
+                FROM Answer
+                INNER JOIN Question ON Question.id = Answer.question_id
+                INNER JOIN User AS Asker ON Asker.id = Question.user_id
+                WHERE Answer.user_id = ?
+                ORDER BY Answer.created_at DESC",
+                param,
+            )
+            .await?;
+        collect_rows(res).await
+    }
+
+    pub async fn create_api_token(&self, token: ApiToken) -> Result<(), Error> {
+        let params = params!(
+            token.id,
+            token.user_id,
+            token.name,
+            token.token_hash,
+            token.scopes,
+            token.created_at.to_rfc3339(),
+        );
+        self.conn
+            .execute(
+                "INSERT INTO ApiToken (id, user_id, name, token_hash, scopes, created_at) VALUES (?,?,?,?,?,?)",
+                params,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_api_tokens_by_user_id(&self, user_id: &str) -> Result<Vec<ApiToken>, Error> {
+        let param = params!(user_id);
+        let res = self
+            .conn
+            .query(
+                "SELECT * FROM ApiToken WHERE user_id = ? ORDER BY created_at DESC",
+                param,
+            )
+            .await?;
+        collect_rows(res).await
+    }
+
+    pub async fn get_api_token_by_hash(&self, token_hash: &str) -> Result<Option<ApiToken>, Error> {
+        let param = params!(token_hash);
+        let mut rows = self
+            .conn
+            .query("SELECT * FROM ApiToken WHERE token_hash = ?", param)
+            .await?;
+        let first = rows.next().await?;
+        Ok(first.map(|r| from_row::<ApiToken>(&r)).transpose()?)
+    }
+
+    pub async fn touch_api_token(&self, id: &str) -> Result<(), Error> {
+        let params = params!(chrono::Utc::now().to_rfc3339(), id);
+        self.conn
+            .execute("UPDATE ApiToken SET last_used_at = ? WHERE id = ?", params)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the token if it belongs to the user, returning whether it existed
+    pub async fn delete_api_token(&self, id: &str, user_id: &str) -> Result<bool, Error> {
+        let params = params!(id, user_id);
+        let affected = self
+            .conn
+            .execute("DELETE FROM ApiToken WHERE id = ? AND user_id = ?", params)
+            .await?;
+        Ok(affected > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    async fn model() -> Model {
+        Database::open(&Location::Memory)
+            .await
+            .unwrap()
+            .model()
+            .await
+            .unwrap()
+    }
+
+    fn user(id: &str, is_askee: bool) -> User {
+        User {
+            id: id.to_string(),
+            username: format!("user-{}", id),
+            discriminator: "0".to_string(),
+            avatar: Some("avatar".to_string()),
+            is_admin: is_askee,
+            is_askee,
+            notify_answers: true,
+            joined_at: Utc::now(),
+            daily_limit: None,
+            last_question_reset: None,
+            banned: false,
+            bonus_questions: 0,
+            questions_used: 0,
+            timezone: None,
+            quota_tat: None,
+        }
+    }
+
+    fn question(id: &str, user_id: &str, askee_id: &str, public: bool) -> Question {
+        Question {
+            id: id.to_string(),
+            title: format!("Question {}", id),
+            body: "body".to_string(),
+            created_at: Utc::now(),
+            public,
+            user_id: user_id.to_string(),
+            askee_id: askee_id.to_string(),
+            channel_id: None,
+            cost: 1,
+        }
+    }
+
+    fn answer(id: &str, question_id: &str, user_id: &str) -> Answer {
+        Answer {
+            id: id.to_string(),
+            body: format!("Answer {}", id),
+            created_at: Utc::now(),
+            user_id: user_id.to_string(),
+            question_id: question_id.to_string(),
+        }
+    }
+
+    fn session(id: &str, user_id: &str, expires_at: DateTime<Utc>) -> Session {
+        Session {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            access_token: "access".to_string(),
+            access_token_nonce: "access-nonce".to_string(),
+            refresh_token: "refresh".to_string(),
+            refresh_token_nonce: "refresh-nonce".to_string(),
+            expires_at,
+            user_agent: None,
+            created_at: Some(Utc::now()),
+            last_seen_at: None,
+        }
+    }
+
+    /// An askee `lp` and two askers `a` and `b`
+    async fn seeded() -> Model {
+        let db = model().await;
+        db.create_user(user("lp", true)).await.unwrap();
+        db.create_user(user("a", false)).await.unwrap();
+        db.create_user(user("b", false)).await.unwrap();
+        db
+    }
+
+    fn oauth() -> BasicClient {
+        BasicClient::new(
+            oauth2::ClientId::new("client".to_string()),
+            None,
+            oauth2::AuthUrl::new("https://discord.com/api/oauth2/authorize".to_string()).unwrap(),
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_users() {
+        let db = seeded().await;
+
+        let user = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(user.username, "user-a");
+        assert!(!user.is_askee);
+        assert!(user.notify_answers);
+        assert!(db.get_user("missing").await.unwrap().is_none());
+
+        assert_eq!(db.get_users().await.unwrap().len(), 3);
+        assert!(matches!(
+            db.create_user(self::user("a", false)).await,
+            Err(Error::Constraint(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_update_profile() {
+        let db = seeded().await;
+
+        db.update_profile("a", "renamed", "0", None).await.unwrap();
+        let user = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(user.username, "renamed");
+        assert_eq!(user.discriminator, "0");
+        assert!(user.avatar.is_none());
+
+        db.update_profile("a", "renamed", "0", Some("a_hash"))
+            .await
+            .unwrap();
+        let user = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(user.avatar.as_deref(), Some("a_hash"));
+    }
+
+    #[tokio::test]
+    async fn test_search_users() {
+        let db = model().await;
+        let mut first = user("lp", true);
+        first.joined_at = Utc::now() - Duration::days(2);
+        db.create_user(first).await.unwrap();
+        let mut second = user("a", false);
+        second.joined_at = Utc::now() - Duration::days(1);
+        db.create_user(second).await.unwrap();
+        db.create_user(user("b", false)).await.unwrap();
+        db.create_question(question("q1", "a", "lp", false))
+            .await
+            .unwrap();
+        db.create_question(question("q2", "a", "lp", true))
+            .await
+            .unwrap();
+
+        let users = db.search_users(None, UserSort::Newest).await.unwrap();
+        let ids = users.iter().map(|u| u.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["b", "a", "lp"]);
+        assert_eq!(users[1].questions_asked, 2);
+        assert_eq!(users[1].questions_received, 0);
+        assert_eq!(users[2].questions_received, 2);
+
+        let users = db.search_users(None, UserSort::Oldest).await.unwrap();
+        assert_eq!(users[0].id, "lp");
+
+        let users = db
+            .search_users(Some("user-l"), UserSort::Newest)
+            .await
+            .unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].id, "lp");
+        assert_eq!(
+            db.search_users(Some("b"), UserSort::Newest)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(db
+            .search_users(Some("nobody"), UserSort::Newest)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut odd = user("c", false);
+        odd.username = "50%_off\\".to_string();
+        db.create_user(odd).await.unwrap();
+        for search in ["%", "_", "\\", "0%_o"] {
+            let users = db
+                .search_users(Some(search), UserSort::Newest)
+                .await
+                .unwrap();
+            let ids = users.iter().map(|u| u.id.as_str()).collect::<Vec<_>>();
+            assert_eq!(ids, vec!["c"], "{}", search);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_user_updates() {
+        let db = seeded().await;
+        db.set_banned("a", true).await.unwrap();
+        db.set_daily_limit("a", Some(3)).await.unwrap();
+
+        let a = db.get_user("a").await.unwrap().unwrap();
+        assert!(a.banned);
+        assert_eq!(a.daily_limit, Some(3));
+        assert!(!db.get_user("b").await.unwrap().unwrap().banned);
+
+        db.set_banned("a", false).await.unwrap();
+        assert!(!db.get_user("a").await.unwrap().unwrap().banned);
+    }
+
+    #[tokio::test]
+    async fn test_answers_by_user_id() {
+        let db = seeded().await;
+        db.create_question(question("q1", "a", "lp", false))
+            .await
+            .unwrap();
+        db.create_question(question("q2", "b", "lp", true))
+            .await
+            .unwrap();
+        db.create_answer(answer("a1", "q1", "lp")).await.unwrap();
+
+        let answers = db.get_answers_by_user_id("lp").await.unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].question_title, "Question q1");
+        assert_eq!(answers[0].asker_username, "user-a");
+        assert!(db.get_answers_by_user_id("a").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_askees() {
+        let db = seeded().await;
+
+        let askees = db.get_askees().await.unwrap();
+        assert_eq!(askees.len(), 1);
+        assert_eq!(askees[0].id, "lp");
+
+        db.set_askee("a", true).await.unwrap();
+        assert_eq!(db.get_askees().await.unwrap().len(), 2);
+        db.set_askee("lp", false).await.unwrap();
+        let askees = db.get_askees().await.unwrap();
+        assert_eq!(askees.len(), 1);
+        assert_eq!(askees[0].id, "a");
+    }
+
+    #[tokio::test]
+    async fn test_notify_answers() {
+        let db = seeded().await;
+
+        db.set_notify_answers("a", false).await.unwrap();
+        assert!(!db.get_user("a").await.unwrap().unwrap().notify_answers);
+        assert!(db.get_user("b").await.unwrap().unwrap().notify_answers);
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let db = seeded().await;
+        let in_an_hour = Utc::now() + Duration::hours(1);
+
+        db.create_session(session("s1", "a", in_an_hour))
+            .await
+            .unwrap();
+        let found = db.get_session("s1").await.unwrap().unwrap();
+        assert_eq!(found.user_id, "a");
+        assert!(db.get_session("missing").await.unwrap().is_none());
+
+        let http = reqwest::Client::new();
+        let active = db
+            .get_active_session(&oauth(), &http, &[0; 32], "s1")
+            .await
+            .unwrap();
+        assert_eq!(active.unwrap().id, "s1");
+        assert!(db
+            .get_active_session(&oauth(), &http, &[0; 32], "missing")
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut updated = session("s1", "a", in_an_hour + Duration::hours(1));
+        updated.access_token = "new-access".to_string();
+        updated.refresh_token = "new-refresh".to_string();
+        updated.refresh_token_nonce = "new-refresh-nonce".to_string();
+        db.update_session(&updated).await.unwrap();
+        let found = db.get_session("s1").await.unwrap().unwrap();
+        assert_eq!(found.access_token, "new-access");
+        assert_eq!(found.refresh_token, "new-refresh");
+        assert_eq!(found.refresh_token_nonce, "new-refresh-nonce");
+        assert_eq!(found.expires_at.timestamp(), updated.expires_at.timestamp());
+
+        db.delete_session("s1").await.unwrap();
+        assert!(db.get_session("s1").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_multiple_sessions() {
+        let db = seeded().await;
+        let in_an_hour = Utc::now() + Duration::hours(1);
+        for id in ["desktop", "phone", "laptop"] {
+            db.create_session(session(id, "a", in_an_hour))
+                .await
+                .unwrap();
+        }
+        db.create_session(session("other", "b", in_an_hour))
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        db.touch_session("phone", now).await.unwrap();
+        let sessions = db.get_sessions_by_user_id("a").await.unwrap();
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions[0].id, "phone");
+        assert_eq!(
+            sessions[0].last_seen_at.unwrap().timestamp(),
+            now.timestamp()
+        );
+
+        // Touching again soon after is skipped
+        db.touch_session("phone", now + Duration::minutes(1))
+            .await
+            .unwrap();
+        let phone = db.get_session("phone").await.unwrap().unwrap();
+        assert_eq!(phone.last_seen_at.unwrap().timestamp(), now.timestamp());
+        let later = now + SESSION_SEEN_INTERVAL + Duration::minutes(1);
+        db.touch_session("phone", later).await.unwrap();
+        let phone = db.get_session("phone").await.unwrap().unwrap();
+        assert_eq!(phone.last_seen_at.unwrap().timestamp(), later.timestamp());
+
+        assert_eq!(db.delete_other_sessions("a", "phone").await.unwrap(), 2);
+        let sessions = db.get_sessions_by_user_id("a").await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, "phone");
+        assert!(db.get_session("other").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_active_session_by_user_id_drops_expired() {
+        let db = seeded().await;
+        let now = Utc::now();
+
+        db.create_session(session("old", "a", now - Duration::hours(1)))
+            .await
+            .unwrap();
+        assert!(db
+            .get_active_session_by_user_id("a")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(db.get_session("old").await.unwrap().is_none());
+
+        db.create_session(session("new", "a", now + Duration::hours(1)))
+            .await
+            .unwrap();
+        let active = db.get_active_session_by_user_id("a").await.unwrap();
+        assert_eq!(active.unwrap().id, "new");
+    }
+
+    #[tokio::test]
+    async fn test_delete_sessions_by_user_id() {
+        let db = seeded().await;
+        let in_an_hour = Utc::now() + Duration::hours(1);
+
+        db.create_session(session("a1", "a", in_an_hour))
+            .await
+            .unwrap();
+        db.create_session(session("a2", "a", in_an_hour))
+            .await
+            .unwrap();
+        db.create_session(session("b1", "b", in_an_hour))
+            .await
+            .unwrap();
+
+        db.delete_sessions_by_user_id("a").await.unwrap();
+        assert!(db.get_session("a1").await.unwrap().is_none());
+        assert!(db.get_session("a2").await.unwrap().is_none());
+        assert!(db.get_session("b1").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_clean_up_expired_sessions() {
+        let db = seeded().await;
+        let now = Utc::now();
+
+        db.create_session(session("old", "a", now - Duration::minutes(1)))
+            .await
+            .unwrap();
+        db.create_session(session("new", "b", now + Duration::hours(1)))
+            .await
+            .unwrap();
+
+        assert_eq!(db.clean_up_expired_sessions().await.unwrap(), 1);
+        assert!(db.get_session("old").await.unwrap().is_none());
+        assert!(db.get_session("new").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_questions() {
+        let db = seeded().await;
+
+        db.create_question(question("q1", "a", "lp", false))
+            .await
+            .unwrap();
+        let found = db.get_question("q1").await.unwrap().unwrap();
+        assert_eq!(found.user_id, "a");
+        assert_eq!(found.askee_id, "lp");
+        assert!(!found.public);
+        assert!(db.get_question("missing").await.unwrap().is_none());
+
+        // The askee must exist
+        assert!(matches!(
+            db.create_question(question("q2", "a", "missing", false))
+                .await,
+            Err(Error::Constraint(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_answers() {
+        let db = seeded().await;
+        db.create_question(question("q1", "a", "lp", false))
+            .await
+            .unwrap();
+
+        assert!(db.get_question_answer("q1").await.unwrap().is_none());
+        db.create_answer(answer("r1", "q1", "lp")).await.unwrap();
+        let found = db.get_question_answer("q1").await.unwrap().unwrap();
+        assert_eq!(found.body, "Answer r1");
+        assert_eq!(found.user_id, "lp");
+    }
+
+    #[tokio::test]
+    async fn test_questions_by_user_id() {
+        let db = seeded().await;
+        let mut older = question("q1", "a", "lp", false);
+        older.created_at = Utc::now() - Duration::hours(1);
+        db.create_question(older).await.unwrap();
+        db.create_question(question("q2", "a", "lp", true))
+            .await
+            .unwrap();
+        db.create_question(question("q3", "b", "lp", true))
+            .await
+            .unwrap();
+        db.create_answer(answer("r1", "q1", "lp")).await.unwrap();
+
+        let questions = db.get_questions_by_user_id("a").await.unwrap();
+        let ids = questions.iter().map(|q| q.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, ["q2", "q1"]);
+        assert!(!questions[0].answered);
+        assert!(questions[0].answer_body.is_none());
+        assert!(questions[1].answered);
+        assert_eq!(questions[1].answer_body.as_deref(), Some("Answer r1"));
+        assert_eq!(questions[1].askee_username, "user-lp");
+    }
+
+    #[tokio::test]
+    async fn test_questions_by_user_id_with_duplicate_answer() {
+        let db = seeded().await;
+        db.create_question(question("q1", "a", "lp", false))
+            .await
+            .unwrap();
+        db.create_answer(answer("r1", "q1", "lp")).await.unwrap();
+
+        let duplicate = db.create_answer(answer("r2", "q1", "lp")).await;
+        assert!(matches!(duplicate, Err(Error::Constraint(_))));
+
+        let questions = db.get_questions_by_user_id("a").await.unwrap();
+        assert_eq!(questions.len(), 1);
+        assert_eq!(questions[0].answer_body.as_deref(), Some("Answer r1"));
+    }
+
+    #[tokio::test]
+    async fn test_decode_errors() {
+        let db = seeded().await;
+        db.conn
+            .execute(
+                "INSERT INTO Question (id, title, body, public, created_at, user_id, askee_id) VALUES ('bad', 'title', 'body', 0, 'yesterday', 'a', 'lp')",
+                (),
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            db.get_question("bad").await,
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(
+            db.get_questions_by_user_id("a").await,
+            Err(Error::Decode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_questions() {
+        let db = seeded().await;
+        db.set_askee("b", true).await.unwrap();
+        db.create_question(question("q1", "a", "lp", false))
+            .await
+            .unwrap();
+        db.create_question(question("q2", "a", "lp", false))
+            .await
+            .unwrap();
+        db.create_question(question("q3", "a", "b", false))
+            .await
+            .unwrap();
+        db.create_answer(answer("r1", "q1", "lp")).await.unwrap();
+
+        let questions = db.get_unanswered_questions("lp").await.unwrap();
+        assert_eq!(questions.len(), 1);
+        assert_eq!(questions[0].question_id, "q2");
+        assert_eq!(questions[0].username, "user-a");
+        assert_eq!(questions[0].askee_username, "user-lp");
+        assert!(!questions[0].answered);
+
+        let questions = db.get_unanswered_questions("b").await.unwrap();
+        assert_eq!(questions.len(), 1);
+        assert_eq!(questions[0].question_id, "q3");
+    }
+
+    #[tokio::test]
+    async fn test_public_questions() {
+        let db = seeded().await;
+        db.create_question(question("q1", "a", "lp", true))
+            .await
+            .unwrap();
+        db.create_question(question("q2", "a", "lp", false))
+            .await
+            .unwrap();
+        db.create_question(question("q3", "b", "lp", true))
+            .await
+            .unwrap();
+        db.create_answer(answer("r1", "q1", "lp")).await.unwrap();
+
+        let ids = |questions: Vec<PublicQuestionSearch>| {
+            let mut ids = questions.into_iter().map(|q| q.id).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(
+            ids(db.get_public_questions(None, None).await.unwrap()),
+            ["q1", "q3"]
+        );
+        assert_eq!(
+            ids(db.get_public_questions(Some("a"), None).await.unwrap()),
+            ["q1"]
+        );
+        assert_eq!(
+            ids(db.get_public_questions(None, Some(true)).await.unwrap()),
+            ["q1"]
+        );
+        assert_eq!(
+            ids(db.get_public_questions(None, Some(false)).await.unwrap()),
+            ["q3"]
+        );
+        assert!(db
+            .get_public_questions(Some("b"), Some(true))
+            .await
+            .unwrap()
+            .is_empty());
+
+        let answered = db.get_public_questions(Some("a"), None).await.unwrap();
+        assert_eq!(answered[0].answer_body.as_deref(), Some("Answer r1"));
+        assert_eq!(answered[0].askee_username, "user-lp");
+    }
+
+    #[tokio::test]
+    async fn test_use_daily_questions_resets_daily() {
+        let db = seeded().await;
+        let day = NaiveDate::from_ymd_opt(2024, 11, 15).unwrap();
+        let next_day = day.succ_opt().unwrap();
+
+        assert!(db.use_daily_questions("a", day, 3, 1).await.unwrap());
+        assert!(db.use_daily_questions("a", day, 3, 2).await.unwrap());
+        assert!(!db.use_daily_questions("a", day, 3, 1).await.unwrap());
+
+        let a = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(a.last_question_reset, Some(day));
+        assert_eq!(a.questions_used_on(day), 3);
+        assert_eq!(a.questions_used_on(next_day), 0);
+
+        // A new day records its own reset date
+        assert!(db.use_daily_questions("a", next_day, 3, 1).await.unwrap());
+        let a = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(a.last_question_reset, Some(next_day));
+        assert_eq!(a.questions_used, 1);
+
+        // Lowering the limit below what was used does not go negative
+        assert!(!db.use_daily_questions("a", next_day, 0, 1).await.unwrap());
+        // Nor does a question costing more than what is left
+        assert!(!db.use_daily_questions("a", next_day, 3, 3).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_reset_questions_used() {
+        let db = seeded().await;
+        let day = NaiveDate::from_ymd_opt(2024, 11, 15).unwrap();
+        let next_day = day.succ_opt().unwrap();
+        assert!(db.use_daily_questions("a", day, 3, 2).await.unwrap());
+        assert_eq!(db.get_users_with_questions_used().await.unwrap().len(), 1);
+
+        // Today's counter is left alone
+        assert!(!db.reset_questions_used("a", day).await.unwrap());
+        assert!(db.reset_questions_used("a", next_day).await.unwrap());
+        let a = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(a.questions_used, 0);
+        assert!(db.get_users_with_questions_used().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_use_bonus_questions() {
+        let db = seeded().await;
+        db.add_bonus_questions("a", 3).await.unwrap();
+
+        assert!(db.use_bonus_questions("a", 2).await.unwrap());
+        assert!(!db.use_bonus_questions("a", 2).await.unwrap());
+        assert!(db.use_bonus_questions("a", 1).await.unwrap());
+        assert_eq!(db.get_user("a").await.unwrap().unwrap().bonus_questions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_use_burst_only_moves_from_current_state() {
+        let db = seeded().await;
+        let now = Utc::now();
+        let later = now + Duration::hours(1);
+
+        assert!(db.use_burst("a", None, now).await.unwrap());
+        // Someone else already moved it
+        assert!(!db.use_burst("a", None, later).await.unwrap());
+        assert!(db.use_burst("a", Some(now), later).await.unwrap());
+
+        let a = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(
+            a.quota_tat.map(|tat| tat.timestamp()),
+            Some(later.timestamp())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_question_costs_since() {
+        let db = seeded().await;
+        let now = Utc::now();
+        for (id, hours_ago, cost) in [("old", 30, 1), ("recent", 5, 2), ("newest", 1, 3)] {
+            let mut q = question(id, "a", "lp", false);
+            q.created_at = now - Duration::hours(hours_ago);
+            q.cost = cost;
+            db.create_question(q).await.unwrap();
+        }
+        db.create_question(question("other", "b", "lp", false))
+            .await
+            .unwrap();
+
+        let costs = db
+            .get_question_costs_since("a", now - Duration::hours(24))
+            .await
+            .unwrap();
+        let costs = costs.iter().map(|(_, cost)| *cost).collect::<Vec<_>>();
+        assert_eq!(costs, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_rolling_bonus_not_counted_in_window() {
+        let db = seeded().await;
+        db.add_bonus_questions("a", 1).await.unwrap();
+        let policy = crate::quota::QuotaPolicy::Rolling;
+        let tz = chrono_tz::UTC;
+        let now = Utc::now();
+
+        // The limit of one pays for the first question, the bonus for the second
+        for (id, expected) in [
+            ("first", QuestionAllowance::Limit),
+            ("second", QuestionAllowance::Bonus),
+        ] {
+            let user = db.get_user("a").await.unwrap().unwrap();
+            let allowance = policy.spend(&db, &user, 1, 1, now, &tz).await.unwrap();
+            assert_eq!(allowance, Some(expected));
+            let mut q = question(id, "a", "lp", false);
+            q.cost = expected.counted_cost(1);
+            db.create_question(q).await.unwrap();
+        }
+
+        let user = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(user.bonus_questions, 0);
+        let usage = policy.usage(&db, &user, 1, now, &tz).await.unwrap();
+        assert_eq!(usage.used, 1);
+    }
+
+    #[tokio::test]
+    async fn test_refund_gives_back_the_spend() {
+        let db = seeded().await;
+        db.add_bonus_questions("a", 1).await.unwrap();
+        let tz = chrono_tz::UTC;
+        let now = Utc::now();
+
+        for policy in [
+            crate::quota::QuotaPolicy::Daily,
+            crate::quota::QuotaPolicy::Burst,
+        ] {
+            let before = db.get_user("a").await.unwrap().unwrap();
+            let allowance = policy.spend(&db, &before, 1, 1, now, &tz).await.unwrap();
+            assert_eq!(allowance, Some(QuestionAllowance::Limit));
+            policy
+                .refund(&db, &before, 1, QuestionAllowance::Limit, 1, now, &tz)
+                .await
+                .unwrap();
+
+            let after = db.get_user("a").await.unwrap().unwrap();
+            let allowance = policy.spend(&db, &after, 1, 1, now, &tz).await.unwrap();
+            assert_eq!(allowance, Some(QuestionAllowance::Limit), "{:?}", policy);
+        }
+
+        let a = db.get_user("a").await.unwrap().unwrap();
+        let policy = crate::quota::QuotaPolicy::Daily;
+        let allowance = policy.spend(&db, &a, 1, 1, now, &tz).await.unwrap();
+        assert_eq!(allowance, Some(QuestionAllowance::Bonus));
+        policy
+            .refund(&db, &a, 1, QuestionAllowance::Bonus, 1, now, &tz)
+            .await
+            .unwrap();
+        let a = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(a.bonus_questions, 1);
+    }
+
+    #[tokio::test]
+    async fn test_set_timezone_keeps_todays_count() {
+        let db = seeded().await;
+        let day = NaiveDate::from_ymd_opt(2024, 11, 15).unwrap();
+        let next_day = day.succ_opt().unwrap();
+        db.use_daily_questions("a", day, 1, 1).await.unwrap();
+
+        db.set_timezone("a", Some("Europe/Paris"), day, next_day)
+            .await
+            .unwrap();
+        let a = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(a.timezone.as_deref(), Some("Europe/Paris"));
+        assert_eq!(a.questions_used_on(next_day), 1);
+        assert!(!db.use_daily_questions("a", next_day, 1, 1).await.unwrap());
+
+        // An old count stays stale
+        db.set_timezone("a", None, day, day).await.unwrap();
+        let a = db.get_user("a").await.unwrap().unwrap();
+        assert!(a.timezone.is_none());
+        assert_eq!(a.last_question_reset, Some(next_day));
+    }
+
+    #[tokio::test]
+    async fn test_daily_limit_resolution() {
+        let db = seeded().await;
+        let a = db.get_user("a").await.unwrap().unwrap();
+        let lp = db.get_user("lp").await.unwrap().unwrap();
+        assert_eq!(
+            db.get_daily_limit(&a).await.unwrap(),
+            crate::GENERIC_DAILY_LIMIT
+        );
+
+        db.set_role_limit(Role::User, Some(5)).await.unwrap();
+        db.set_role_limit(Role::User, Some(4)).await.unwrap();
+        assert_eq!(db.get_daily_limit(&a).await.unwrap(), 4);
+        assert_eq!(
+            db.get_daily_limit(&lp).await.unwrap(),
+            crate::GENERIC_DAILY_LIMIT
+        );
+
+        db.set_daily_limit("a", Some(20)).await.unwrap();
+        let a = db.get_user("a").await.unwrap().unwrap();
+        assert_eq!(db.get_daily_limit(&a).await.unwrap(), 20);
+
+        db.set_role_limit(Role::User, None).await.unwrap();
+        assert!(db.get_role_limits().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_limit_boosts() {
+        let db = seeded().await;
+        let now = Utc::now();
+        let boost = |id: &str, ends_at| LimitBoost {
+            id: id.to_string(),
+            extra_questions: 5,
+            ends_at,
+            created_by: "lp".to_string(),
+            created_at: now,
+        };
+        db.create_limit_boost(boost("over", now - Duration::hours(1)))
+            .await
+            .unwrap();
+        db.create_limit_boost(boost("running", now + Duration::hours(1)))
+            .await
+            .unwrap();
+
+        let active = db.get_active_limit_boosts(now).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].id, "running");
+
+        assert!(db.end_limit_boost("running", now).await.unwrap());
+        assert!(db.get_active_limit_boosts(now).await.unwrap().is_empty());
+        assert!(!db.end_limit_boost("running", now).await.unwrap());
+        assert!(!db.end_limit_boost("over", now).await.unwrap());
+    }
+
+    fn api_token(id: &str, user_id: &str, token_hash: &str) -> ApiToken {
+        ApiToken {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            name: format!("Token {}", id),
+            token_hash: token_hash.to_string(),
+            scopes: "read ask".to_string(),
+            created_at: Utc::now(),
+            last_used_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_tokens() {
+        let db = seeded().await;
+        db.create_api_token(api_token("t1", "a", "hash-1"))
+            .await
+            .unwrap();
+        db.create_api_token(api_token("t2", "a", "hash-2"))
+            .await
+            .unwrap();
+        db.create_api_token(api_token("t3", "b", "hash-3"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            db.create_api_token(api_token("t4", "b", "hash-3")).await,
+            Err(Error::Constraint(_))
+        ));
+
+        assert_eq!(db.get_api_tokens_by_user_id("a").await.unwrap().len(), 2);
+
+        let found = db.get_api_token_by_hash("hash-1").await.unwrap().unwrap();
+        assert_eq!(found.id, "t1");
+        assert_eq!(found.scopes, "read ask");
+        assert!(found.last_used_at.is_none());
+        assert!(db.get_api_token_by_hash("nope").await.unwrap().is_none());
+
+        db.touch_api_token("t1").await.unwrap();
+        let found = db.get_api_token_by_hash("hash-1").await.unwrap().unwrap();
+        assert!(found.last_used_at.is_some());
+
+        // Only the owner can revoke a token
+        assert!(!db.delete_api_token("t1", "b").await.unwrap());
+        assert!(db.delete_api_token("t1", "a").await.unwrap());
+        assert!(db.get_api_token_by_hash("hash-1").await.unwrap().is_none());
+        assert_eq!(db.get_api_tokens_by_user_id("a").await.unwrap().len(), 1);
     }
 }
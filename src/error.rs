@@ -2,55 +2,134 @@ use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
+use chrono::DateTime;
+use chrono_tz::Tz;
+use std::borrow::Cow;
 use tracing::error;
 
+use crate::db;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
     Auth(String),
     Config(String),
-    Db(libsql::Error),
+    Db(db::Error),
     UserAlreadyExists,
     InvalidQuestionTitle,
     InvalidQuestionBody,
-    DailyLimitReached,
+    InvalidAnswerBody,
+    /// Carries when the quota resets, in the user's timezone
+    DailyLimitReached {
+        resets_at: DateTime<Tz>,
+    },
     QuestionNotFound,
+    AnswerNotFound,
+    NotFound,
+    InvalidAskee,
     Unauthorized,
+    Banned,
+    MissingScope,
+    InvalidTokenName,
+    InvalidLimit,
+    InvalidTimezone,
     AnswerAlreadyExists,
+    /// The request itself could not be read, such as a malformed JSON body
+    InvalidRequest {
+        status: StatusCode,
+        message: String,
+    },
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        error!("Error: {:?}", self);
-        let (status, error_message) = match self {
+impl Error {
+    /// Status code and user facing message for this error
+    pub fn status_and_message(&self) -> (StatusCode, Cow<'static, str>) {
+        match self {
+            Error::DailyLimitReached { resets_at } => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Question limit reached. Come back after {} to submit another question",
+                    resets_at.format("%-I:%M %p %Z on %A, %B %-d")
+                )
+                .into(),
+            ),
+            Error::InvalidRequest { status, message } => (*status, message.clone().into()),
+            _ => {
+                let (status, message) = self.static_status_and_message();
+                (status, message.into())
+            }
+        }
+    }
+
+    fn static_status_and_message(&self) -> (StatusCode, &'static str) {
+        match self {
             Error::Auth(_) => (StatusCode::UNAUTHORIZED, "Unauthorized"),
             Error::AnswerAlreadyExists => (
                 StatusCode::BAD_REQUEST,
                 "Answer already exists for this question",
             ),
             Error::Config(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"),
+            Error::Db(db::Error::Constraint(_)) => (
+                StatusCode::CONFLICT,
+                "This conflicts with something that already exists",
+            ),
             Error::Db(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"),
             Error::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized"),
+            Error::Banned => (StatusCode::FORBIDDEN, "Your account has been banned"),
+            Error::MissingScope => (
+                StatusCode::FORBIDDEN,
+                "This API token does not have the scope required for this request",
+            ),
+            Error::InvalidLimit => (
+                StatusCode::BAD_REQUEST,
+                "Invalid Limit. Limits must be whole numbers up to 1,000,000 and boosts must last between 1 hour and 30 days",
+            ),
+            Error::InvalidTimezone => (
+                StatusCode::BAD_REQUEST,
+                "Invalid Timezone. Pick a timezone from the list",
+            ),
+            Error::InvalidTokenName => (
+                StatusCode::BAD_REQUEST,
+                "Invalid Token. Name must be between 1 and 50 characters and at least one scope must be picked",
+            ),
             Error::InvalidQuestionTitle => (
                 StatusCode::BAD_REQUEST,
                 "Invalid Question Title. Title must be between 5 and 100 characters",
             ),
             Error::InvalidQuestionBody => (
                 StatusCode::BAD_REQUEST,
-                "Invalid Question Body. Body must be between 5 and 100 characters",
+                "Invalid Question Body. Body must be at most 1000 characters",
             ),
-            Error::DailyLimitReached => (
+            Error::InvalidAnswerBody => (
                 StatusCode::BAD_REQUEST,
-                "Daily question limit reached. Come back tomorrow to submit another question",
+                "Invalid Answer Body. Answer must be between 5 and 2000 characters",
             ),
+            Error::DailyLimitReached { .. } => (
+                StatusCode::BAD_REQUEST,
+                "Question limit reached. Come back later to submit another question",
+            ),
+            Error::InvalidRequest { .. } => (StatusCode::BAD_REQUEST, "Invalid request"),
             Error::QuestionNotFound => (StatusCode::NOT_FOUND, "Question not found"),
+            Error::AnswerNotFound => (StatusCode::NOT_FOUND, "Answer not found"),
+            Error::NotFound => (StatusCode::NOT_FOUND, "Not found"),
+            Error::InvalidAskee => (
+                StatusCode::BAD_REQUEST,
+                "Invalid askee. Pick someone who is accepting questions",
+            ),
             Error::UserAlreadyExists => (StatusCode::BAD_REQUEST, "User Already Exists"),
-        };
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        error!("Error: {:?}", self);
+        let (status, error_message) = self.status_and_message();
 
         if status == StatusCode::NOT_FOUND {
             let template = crate::NotFoundTemplate {
-                message: error_message.to_string(),
+                message: error_message.into_owned(),
             };
             template.into_response()
         } else {
@@ -59,8 +138,14 @@ impl IntoResponse for Error {
     }
 }
 
+impl From<db::Error> for Error {
+    fn from(e: db::Error) -> Self {
+        Error::Db(e)
+    }
+}
+
 impl From<libsql::Error> for Error {
     fn from(e: libsql::Error) -> Self {
-        Error::Db(e)
+        Error::Db(e.into())
     }
 }
@@ -0,0 +1,196 @@
+use crate::error::{Error, Result};
+use axum::http::StatusCode;
+use axum_extra::extract::cookie::{Cookie, SameSite, SignedCookieJar};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
+
+const FLASH_COOKIE: &str = "asklp_flash";
+/// Browsers drop cookies over 4KB, this leaves room for the message, the signature and the
+/// cookie attributes. Counted once percent-encoded, as the cookie is sent.
+const MAX_FORM_BYTES: usize = 3000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Success,
+    Error,
+}
+
+/// A message shown once on the next page, kept in a signed cookie across the redirect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Flash {
+    pub level: Level,
+    pub message: String,
+    /// What the user submitted, so the form can be filled back in after an error
+    form: Option<serde_json::Value>,
+}
+
+impl Flash {
+    pub fn success(message: impl Into<String>) -> Self {
+        Self {
+            level: Level::Success,
+            message: message.into(),
+            form: None,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            level: Level::Error,
+            message: message.into(),
+            form: None,
+        }
+    }
+
+    /// Errors the user can fix, such as validation and quota errors, become a message on the
+    /// page they came from. Anything else is handed back to render an error page.
+    pub fn from_error(e: Error) -> Result<Self> {
+        let (status, message) = e.status_and_message();
+        match status {
+            StatusCode::BAD_REQUEST | StatusCode::CONFLICT => Ok(Self::error(message)),
+            _ => Err(e),
+        }
+    }
+
+    /// Keeps the submitted form, with its longest fields cut short when it does not fit in the
+    /// cookie
+    pub fn with_form(mut self, form: &impl Serialize) -> Self {
+        self.form = serde_json::to_value(form).ok().and_then(fit_form);
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == Level::Error
+    }
+
+    /// The form submitted along with this message, if it was of type `T`
+    pub fn form<T: DeserializeOwned>(&self) -> Option<T> {
+        self.form
+            .clone()
+            .and_then(|form| serde_json::from_value(form).ok())
+    }
+
+    /// Sets the message for the next page
+    pub fn set(self, jar: SignedCookieJar) -> SignedCookieJar {
+        let value = match serde_json::to_string(&self) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to serialize flash message: {:?}", e);
+                return jar;
+            }
+        };
+        let cookie = Cookie::build((FLASH_COOKIE, value))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        jar.add(cookie)
+    }
+
+    /// Reads the message left by the previous request and clears it so it only shows once
+    pub fn take(jar: SignedCookieJar) -> (SignedCookieJar, Option<Flash>) {
+        let Some(cookie) = jar.get(FLASH_COOKIE) else {
+            return (jar, None);
+        };
+        let flash = serde_json::from_str(cookie.value()).ok();
+        let jar = jar.remove(Cookie::build(FLASH_COOKIE).path("/"));
+        (jar, flash)
+    }
+}
+
+/// Length of `json` in the `Set-Cookie` header, where the jar percent-encodes it
+fn encoded_len(json: &str) -> usize {
+    // Less the `=` between the empty name and the value
+    Cookie::new("", json).encoded().to_string().len() - 1
+}
+
+/// Truncates the longest string field until the encoded form is at most `MAX_FORM_BYTES`.
+/// Gives up on the form when there is nothing left to truncate.
+fn fit_form(mut form: serde_json::Value) -> Option<serde_json::Value> {
+    loop {
+        let len = encoded_len(&serde_json::to_string(&form).ok()?);
+        if len <= MAX_FORM_BYTES {
+            return Some(form);
+        }
+        let longest = form
+            .as_object_mut()?
+            .values_mut()
+            .filter_map(|value| match value {
+                serde_json::Value::String(value) => Some(value),
+                _ => None,
+            })
+            .max_by_key(|value| value.len())
+            .filter(|value| !value.is_empty())?;
+        // Escaping and encoding grow characters unevenly, each one is measured as it is sent
+        let quotes = encoded_len("\"\"");
+        let budget = encoded_len(&serde_json::to_string(longest).ok()?)
+            .saturating_sub(quotes + len - MAX_FORM_BYTES);
+        let mut used = 0;
+        let end = longest
+            .char_indices()
+            .find(|(_, c)| {
+                used += serde_json::to_string(c).map_or(budget + 1, |c| encoded_len(&c) - quotes);
+                used > budget
+            })
+            .map_or(0, |(i, _)| i);
+        longest.truncate(end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::header::SET_COOKIE, response::IntoResponse};
+    use axum_extra::extract::cookie::Key;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Form {
+        title: String,
+    }
+
+    #[test]
+    fn test_flash_round_trip() {
+        let jar = SignedCookieJar::new(Key::generate());
+        let form = Form {
+            title: "Hello; \"world\"".to_string(),
+        };
+        let jar = Flash::error("Nope").with_form(&form).set(jar);
+
+        let (jar, flash) = Flash::take(jar);
+        let flash = flash.unwrap();
+        assert!(flash.is_error());
+        assert_eq!(flash.message, "Nope");
+        assert_eq!(flash.form::<Form>(), Some(form));
+
+        let (_, flash) = Flash::take(jar);
+        assert!(flash.is_none());
+    }
+
+    #[test]
+    fn test_long_form_fits_in_the_cookie() {
+        let jar = SignedCookieJar::new(Key::generate());
+        // Every byte but the letters is percent-encoded to three
+        let form = Form {
+            title: "é \"{}, ".repeat(1000),
+        };
+        let message = Error::InvalidQuestionBody.status_and_message().1;
+        let jar = Flash::error(message).with_form(&form).set(jar);
+        let response = jar.clone().into_response();
+        let header = response.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(header.len() < 4000, "{} bytes", header.len());
+
+        let (_, flash) = Flash::take(jar);
+        let title = flash.unwrap().form::<Form>().unwrap().title;
+        assert!(!title.is_empty());
+        assert!(form.title.starts_with(&title));
+    }
+
+    #[test]
+    fn test_only_fixable_errors_are_flashed() {
+        let flash = Flash::from_error(Error::InvalidQuestionTitle).unwrap();
+        assert!(flash.is_error());
+        assert!(flash.message.starts_with("Invalid Question Title"));
+
+        assert!(Flash::from_error(Error::NotFound).is_err());
+        assert!(Flash::from_error(Error::Unauthorized).is_err());
+    }
+}
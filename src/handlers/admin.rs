@@ -0,0 +1,461 @@
+use crate::{
+    db::{self, AnswerWithQuestion, LimitBoost, Role, RoleLimit, UserSort, UserSummary},
+    error::{Error, Result},
+    flash::Flash,
+    jobs::JobStatus,
+    AppState,
+};
+use askama::Template;
+use axum::{
+    extract::{Extension, Form, Path, Query, State},
+    response::Redirect,
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+use serde::Deserialize;
+
+use super::app::{avatar_url, quota, user_image_url, AskeeForm, QuestionAnswered, Quota};
+
+pub fn require_admin(user: &db::User) -> Result<()> {
+    if user.is_admin {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UsersQuery {
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: UserSort,
+}
+
+impl UsersQuery {
+    /// Blank searches from the form count as no search
+    pub fn search(&self) -> Option<&str> {
+        self.q.as_deref().map(str::trim).filter(|q| !q.is_empty())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "app/users.html")]
+pub struct UsersTemplate {
+    user: db::User,
+    image_url: String,
+    users: Vec<UserSummary>,
+    q: String,
+    sort: UserSort,
+    flash: Option<Flash>,
+}
+
+impl UsersTemplate {
+    fn user_image_url(&self, summary: &UserSummary) -> String {
+        avatar_url(
+            &summary.id,
+            &summary.discriminator,
+            summary.avatar.as_deref(),
+        )
+    }
+}
+
+pub async fn users(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Query(query): Query<UsersQuery>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, UsersTemplate)> {
+    require_admin(&user)?;
+
+    let users = state.db.search_users(query.search(), query.sort).await?;
+    let (jar, flash) = Flash::take(jar);
+
+    Ok((
+        jar,
+        UsersTemplate {
+            image_url: user_image_url(&user),
+            user,
+            users,
+            q: query.search().unwrap_or_default().to_string(),
+            sort: query.sort,
+            flash,
+        },
+    ))
+}
+
+#[derive(Template)]
+#[template(path = "app/user.html")]
+pub struct UserTemplate {
+    user: db::User,
+    image_url: String,
+    member: db::User,
+    member_image_url: String,
+    /// What the member gets without an override of their own
+    default_limit: u64,
+    questions: Vec<QuestionAnswered>,
+    answers: Vec<AnswerWithQuestion>,
+    quota: Quota,
+    flash: Option<Flash>,
+}
+
+async fn get_member(state: &AppState, id: &str) -> Result<db::User> {
+    state.db.get_user(id).await?.ok_or(Error::NotFound)
+}
+
+/// Back to the member's page with a message about what was done
+fn member_redirect(jar: SignedCookieJar, id: &str, flash: Flash) -> (SignedCookieJar, Redirect) {
+    (flash.set(jar), Redirect::to(&format!("/app/user/{}", id)))
+}
+
+pub async fn user(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Path(id): Path<String>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, UserTemplate)> {
+    require_admin(&user)?;
+
+    let member = get_member(&state, &id).await?;
+    let questions = state.db.get_questions_by_user_id(&member.id).await?;
+    let answers = state.db.get_answers_by_user_id(&member.id).await?;
+    let quota = quota(&state, &member).await?;
+    let default_limit = state
+        .db
+        .get_daily_limit(&db::User {
+            daily_limit: None,
+            ..member.clone()
+        })
+        .await?;
+
+    let (jar, flash) = Flash::take(jar);
+
+    Ok((
+        jar,
+        UserTemplate {
+            default_limit,
+            image_url: user_image_url(&user),
+            user,
+            member_image_url: user_image_url(&member),
+            member,
+            questions,
+            answers,
+            quota,
+            flash,
+        },
+    ))
+}
+
+/// Well past any real use, keeps limits and their sums clear of integer overflows
+const MAX_LIMIT: u64 = 1_000_000;
+
+/// Blank limit fields mean "use the default"
+fn parse_limit(value: &str) -> Result<Option<u64>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match value.parse() {
+        Ok(limit) if limit <= MAX_LIMIT => Ok(Some(limit)),
+        _ => Err(Error::InvalidLimit),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LimitForm {
+    daily_limit: String,
+}
+
+pub async fn set_limit(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Path(id): Path<String>,
+    jar: SignedCookieJar,
+    Form(form): Form<LimitForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    require_admin(&user)?;
+
+    let member = get_member(&state, &id).await?;
+    let daily_limit = match parse_limit(&form.daily_limit) {
+        Ok(daily_limit) => daily_limit,
+        Err(e) => return Ok(member_redirect(jar, &member.id, Flash::from_error(e)?)),
+    };
+    state.db.set_daily_limit(&member.id, daily_limit).await?;
+
+    let flash = match daily_limit {
+        Some(limit) => Flash::success(format!("{}'s limit is now {}", member.username, limit)),
+        None => Flash::success(format!("{} is back on the default limit", member.username)),
+    };
+    Ok(member_redirect(jar, &member.id, flash))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BonusForm {
+    bonus_questions: String,
+}
+
+pub async fn grant_bonus(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Path(id): Path<String>,
+    jar: SignedCookieJar,
+    Form(form): Form<BonusForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    require_admin(&user)?;
+
+    let member = get_member(&state, &id).await?;
+    let bonus_questions = parse_limit(&form.bonus_questions)
+        .and_then(|bonus| bonus.filter(|bonus| *bonus > 0).ok_or(Error::InvalidLimit));
+    let bonus_questions = match bonus_questions {
+        Ok(bonus_questions) => bonus_questions,
+        Err(e) => return Ok(member_redirect(jar, &member.id, Flash::from_error(e)?)),
+    };
+    state
+        .db
+        .add_bonus_questions(&member.id, bonus_questions)
+        .await?;
+
+    let flash = Flash::success(format!(
+        "Granted {} bonus questions to {}",
+        bonus_questions, member.username
+    ));
+    Ok(member_redirect(jar, &member.id, flash))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BanForm {
+    banned: bool,
+}
+
+/// Bans or unbans the user. Banning also signs them out everywhere.
+pub async fn set_banned(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Path(id): Path<String>,
+    jar: SignedCookieJar,
+    Form(form): Form<BanForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    require_admin(&user)?;
+
+    let member = get_member(&state, &id).await?;
+    if member.id == user.id {
+        return Err(Error::Unauthorized);
+    }
+    state.db.set_banned(&member.id, form.banned).await?;
+    if form.banned {
+        state.db.delete_sessions_by_user_id(&member.id).await?;
+    }
+
+    let flash = match form.banned {
+        true => Flash::success(format!("{} was banned", member.username)),
+        false => Flash::success(format!("{} was unbanned", member.username)),
+    };
+    Ok(member_redirect(jar, &member.id, flash))
+}
+
+pub async fn delete_sessions(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Path(id): Path<String>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, Redirect)> {
+    require_admin(&user)?;
+
+    let member = get_member(&state, &id).await?;
+    state.db.delete_sessions_by_user_id(&member.id).await?;
+
+    let flash = Flash::success(format!("{} was signed out everywhere", member.username));
+    Ok(member_redirect(jar, &member.id, flash))
+}
+
+/// Lets an admin open or close another user's inbox
+pub async fn set_askee(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Path(id): Path<String>,
+    jar: SignedCookieJar,
+    Form(form): Form<AskeeForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    require_admin(&user)?;
+
+    let member = get_member(&state, &id).await?;
+    state.db.set_askee(&member.id, form.askee).await?;
+
+    let flash = match form.askee {
+        true => Flash::success(format!("{} is now accepting questions", member.username)),
+        false => Flash::success(format!("{} no longer accepts questions", member.username)),
+    };
+    Ok(member_redirect(jar, &member.id, flash))
+}
+
+#[derive(Template)]
+#[template(path = "app/limits.html")]
+pub struct LimitsTemplate {
+    user: db::User,
+    image_url: String,
+    role_limits: Vec<RoleLimit>,
+    boosts: Vec<LimitBoost>,
+    flash: Option<Flash>,
+}
+
+impl LimitsTemplate {
+    fn role_limit(&self, role: &Role) -> String {
+        self.role_limits
+            .iter()
+            .find(|limit| limit.role == *role)
+            .map(|limit| limit.daily_limit.to_string())
+            .unwrap_or_default()
+    }
+}
+
+pub async fn limits(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, LimitsTemplate)> {
+    require_admin(&user)?;
+
+    let role_limits = state.db.get_role_limits().await?;
+    let boosts = state.db.get_active_limit_boosts(chrono::Utc::now()).await?;
+    let (jar, flash) = Flash::take(jar);
+
+    Ok((
+        jar,
+        LimitsTemplate {
+            image_url: user_image_url(&user),
+            user,
+            role_limits,
+            boosts,
+            flash,
+        },
+    ))
+}
+
+/// Back to the limits page with a message about what was done
+fn limits_redirect(jar: SignedCookieJar, flash: Flash) -> (SignedCookieJar, Redirect) {
+    (flash.set(jar), Redirect::to("/app/admin/limits"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleLimitsForm {
+    user: String,
+    askee: String,
+    admin: String,
+}
+
+pub async fn set_role_limits(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+    Form(form): Form<RoleLimitsForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    require_admin(&user)?;
+
+    let limits = [
+        (Role::User, &form.user),
+        (Role::Askee, &form.askee),
+        (Role::Admin, &form.admin),
+    ]
+    .into_iter()
+    .map(|(role, limit)| Ok((role, parse_limit(limit)?)))
+    .collect::<Result<Vec<_>>>();
+    let limits = match limits {
+        Ok(limits) => limits,
+        Err(e) => return Ok(limits_redirect(jar, Flash::from_error(e)?)),
+    };
+    for (role, limit) in limits {
+        state.db.set_role_limit(role, limit).await?;
+    }
+
+    Ok(limits_redirect(jar, Flash::success("Role limits saved")))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BoostForm {
+    extra_questions: u64,
+    hours: u32,
+}
+
+/// Raises everyone's limit for the next few hours
+pub async fn create_boost(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+    Form(form): Form<BoostForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    require_admin(&user)?;
+
+    if form.extra_questions == 0
+        || form.extra_questions > MAX_LIMIT
+        || form.hours == 0
+        || form.hours > 24 * 30
+    {
+        return Ok(limits_redirect(
+            jar,
+            Flash::from_error(Error::InvalidLimit)?,
+        ));
+    }
+
+    let now = chrono::Utc::now();
+    state
+        .db
+        .create_limit_boost(LimitBoost {
+            id: uuid::Uuid::new_v4().to_string(),
+            extra_questions: form.extra_questions,
+            ends_at: now + chrono::Duration::hours(form.hours.into()),
+            created_by: user.id.clone(),
+            created_at: now,
+        })
+        .await?;
+
+    let flash = Flash::success(format!(
+        "Everyone can ask {} more questions for the next {} hours",
+        form.extra_questions, form.hours
+    ));
+    Ok(limits_redirect(jar, flash))
+}
+
+pub async fn end_boost(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Path(id): Path<String>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, Redirect)> {
+    require_admin(&user)?;
+
+    let flash = match state.db.end_limit_boost(&id, chrono::Utc::now()).await? {
+        true => Flash::success("Boost ended"),
+        false => Flash::error("That boost already ended"),
+    };
+    Ok(limits_redirect(jar, flash))
+}
+
+#[derive(Template)]
+#[template(path = "app/jobs.html")]
+pub struct JobsTemplate {
+    user: db::User,
+    image_url: String,
+    jobs: Vec<JobStatus>,
+    /// Notifications waiting for the retry job
+    failed_notifications: usize,
+    flash: Option<Flash>,
+}
+
+pub async fn jobs(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, JobsTemplate)> {
+    require_admin(&user)?;
+
+    let (jar, flash) = Flash::take(jar);
+
+    Ok((
+        jar,
+        JobsTemplate {
+            image_url: user_image_url(&user),
+            user,
+            jobs: state.jobs.statuses(),
+            failed_notifications: state.notifier.failed_count(),
+            flash,
+        },
+    ))
+}
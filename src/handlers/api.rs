@@ -0,0 +1,355 @@
+use crate::{
+    auth::{Scope, Scopes},
+    db,
+    error::Error,
+    handlers::admin::{require_admin, UsersQuery},
+    handlers::app::{
+        answer_question, ask_question, list_questions, quota, NewAnswerForm, NewQuestionForm,
+        QuestionUser, Quota,
+    },
+    AppState,
+};
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequest, FromRequestParts, Path, Query, Request, State},
+    http::{header::RETRY_AFTER, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use tracing::error;
+
+pub type Result<T> = std::result::Result<T, ApiError>;
+
+/// Renders an [`Error`] as a JSON body instead of an HTML page
+#[derive(Debug)]
+pub struct ApiError(Error);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        error!("Error: {:?}", self.0);
+        let (mut status, message) = self.0.status_and_message();
+        // Clients can wait out the quota instead of treating it as a bad request
+        let retry_after = match &self.0 {
+            Error::DailyLimitReached { resets_at } => {
+                status = StatusCode::TOO_MANY_REQUESTS;
+                let wait = resets_at.with_timezone(&chrono::Utc) - chrono::Utc::now();
+                Some((wait.num_milliseconds().max(0) + 999) / 1000)
+            }
+            _ => None,
+        };
+        let body = json!({
+            "status": status.as_u16(),
+            "error": message,
+        });
+        let mut response = (status, Json(body)).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        ApiError(e)
+    }
+}
+
+impl From<db::Error> for ApiError {
+    fn from(e: db::Error) -> Self {
+        ApiError(e.into())
+    }
+}
+
+/// What axum rejected the request with, as the API's JSON error body
+fn rejected(status: StatusCode, message: String) -> ApiError {
+    ApiError(Error::InvalidRequest { status, message })
+}
+
+/// [`Json`] answering a body it cannot read with an [`ApiError`], like the rest of the API
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|rejection| rejected(rejection.status(), rejection.body_text()))?;
+        Ok(ApiJson(value))
+    }
+}
+
+/// [`Path`] answering an unreadable path with an [`ApiError`]
+pub struct ApiPath<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        let Path(value) = Path::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| rejected(rejection.status(), rejection.body_text()))?;
+        Ok(ApiPath(value))
+    }
+}
+
+/// [`Query`] answering an unreadable query string with an [`ApiError`]
+pub struct ApiQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| rejected(rejection.status(), rejection.body_text()))?;
+        Ok(ApiQuery(value))
+    }
+}
+
+#[derive(Serialize)]
+pub struct Me {
+    user: db::User,
+    quota: Quota,
+}
+
+pub async fn me(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<Json<Me>> {
+    scopes.require(Scope::Read)?;
+    let quota = quota(&state, &user).await?;
+    Ok(Json(Me { user, quota }))
+}
+
+#[derive(Serialize)]
+pub struct Askee {
+    id: String,
+    username: String,
+}
+
+pub async fn askees(
+    State(state): State<AppState>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<Json<Vec<Askee>>> {
+    scopes.require(Scope::Read)?;
+    let askees = state
+        .db
+        .get_askees()
+        .await?
+        .into_iter()
+        .map(|askee| Askee {
+            id: askee.id,
+            username: askee.username,
+        })
+        .collect();
+    Ok(Json(askees))
+}
+
+/// The admin user directory, searchable with `?q=` and sorted with `?sort=newest|oldest`
+pub async fn admin_users(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Extension(scopes): Extension<Scopes>,
+    ApiQuery(query): ApiQuery<UsersQuery>,
+) -> Result<Json<Vec<db::UserSummary>>> {
+    scopes.require(Scope::Read)?;
+    require_admin(&user)?;
+    Ok(Json(
+        state.db.search_users(query.search(), query.sort).await?,
+    ))
+}
+
+/// The askee's unanswered inbox, or the questions the user asked
+pub async fn questions(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<Json<Vec<QuestionUser>>> {
+    scopes.require(Scope::Read)?;
+    Ok(Json(list_questions(&state, &user).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewQuestion {
+    title: String,
+    body: Option<String>,
+    #[serde(default)]
+    public: bool,
+    askee_id: String,
+}
+
+pub async fn create_question(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Extension(scopes): Extension<Scopes>,
+    ApiJson(input): ApiJson<NewQuestion>,
+) -> Result<(StatusCode, Json<db::Question>)> {
+    scopes.require(Scope::Ask)?;
+    let form = NewQuestionForm {
+        title: input.title,
+        body: input.body,
+        public: input.public,
+        askee_id: input.askee_id,
+    };
+    let question = ask_question(&state, &user, form, None).await?;
+    Ok((StatusCode::CREATED, Json(question)))
+}
+
+#[derive(Serialize)]
+pub struct QuestionWithAnswer {
+    #[serde(flatten)]
+    question: db::Question,
+    answer: Option<db::Answer>,
+}
+
+/// Fetches a question the user asked or was asked, or any public question
+async fn visible_question(state: &AppState, user: &db::User, id: &str) -> Result<db::Question> {
+    let question = state
+        .db
+        .get_question(id)
+        .await?
+        .ok_or(Error::QuestionNotFound)?;
+
+    let visible = question.public
+        || user.is_admin
+        || question.user_id == user.id
+        || question.askee_id == user.id;
+    if !visible {
+        // Do not reveal that a private question exists
+        return Err(Error::QuestionNotFound.into());
+    }
+    Ok(question)
+}
+
+pub async fn question(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    ApiPath(id): ApiPath<String>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<Json<QuestionWithAnswer>> {
+    scopes.require(Scope::Read)?;
+    let question = visible_question(&state, &user, &id).await?;
+    let answer = state.db.get_question_answer(&question.id).await?;
+    Ok(Json(QuestionWithAnswer { question, answer }))
+}
+
+pub async fn answer(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    ApiPath(id): ApiPath<String>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<Json<db::Answer>> {
+    scopes.require(Scope::Read)?;
+    let question = visible_question(&state, &user, &id).await?;
+    let answer = state
+        .db
+        .get_question_answer(&question.id)
+        .await?
+        .ok_or(Error::AnswerNotFound)?;
+    Ok(Json(answer))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewAnswer {
+    body: String,
+}
+
+pub async fn create_answer(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    ApiPath(id): ApiPath<String>,
+    Extension(scopes): Extension<Scopes>,
+    ApiJson(input): ApiJson<NewAnswer>,
+) -> Result<(StatusCode, Json<db::Answer>)> {
+    scopes.require(Scope::Answer)?;
+    let form = NewAnswerForm { body: input.body };
+    let answer = answer_question(&state, &user, &id, form).await?;
+    Ok((StatusCode::CREATED, Json(answer)))
+}
+
+pub async fn not_found() -> ApiError {
+    ApiError(Error::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unreadable_requests_get_json_errors() {
+        use axum::{body::Body, routing};
+        use tower::ServiceExt;
+
+        async fn create(ApiJson(input): ApiJson<NewAnswer>) -> String {
+            input.body
+        }
+        let app = axum::Router::new().route("/answer", routing::post(create));
+
+        for (content_type, body, status) in [
+            ("application/json", "{\"body\": ", StatusCode::BAD_REQUEST),
+            (
+                "application/json",
+                "{\"text\": \"hi\"}",
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                "text/plain",
+                "{\"body\": \"hi\"}",
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+        ] {
+            let request = axum::http::Request::post("/answer")
+                .header("content-type", content_type)
+                .body(Body::from(body))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["status"], status.as_u16());
+            assert!(body["error"].is_string());
+        }
+    }
+
+    #[test]
+    fn test_limit_reached_is_too_many_requests() {
+        let resets_at =
+            chrono::Utc::now().with_timezone(&chrono_tz::UTC) + chrono::Duration::hours(1);
+        let response = ApiError::from(Error::DailyLimitReached { resets_at }).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after = response.headers()[RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse::<i64>()
+            .unwrap();
+        assert!((3599..=3600).contains(&retry_after));
+
+        let response = ApiError::from(Error::InvalidQuestionTitle).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.headers().get(RETRY_AFTER).is_none());
+    }
+}
@@ -1,29 +1,46 @@
+use crate::time::{parse_timezone, quota_day};
 use crate::{
-    db,
+    bot, db,
     error::{Error, Result},
-    twilio::send_sms,
-    AppTemplate, DISCORD_AVATAR_URL,
+    flash::Flash,
+    notify::Notification,
+    AppTemplate, DISCORD_AVATAR_URL, DISCORD_DEFAULT_AVATAR_URL,
 };
 use crate::{AppState, NewQuestionTemplate};
 use askama::Template;
 use axum::{
-    extract::{Extension, Form, Path, State},
+    extract::{Extension, Form, Path, Query, State},
     response::Redirect,
 };
+use axum_extra::extract::cookie::SignedCookieJar;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use tracing::error;
 
-fn user_image_url(user: &db::User) -> String {
-    let ext = match user.avatar.starts_with("a_") {
+/// Users without an avatar get one of Discord's defaults, picked the way Discord does: from the
+/// discriminator for legacy usernames, from the id for accounts on the new username system
+pub fn avatar_url(user_id: &str, discriminator: &str, avatar: Option<&str>) -> String {
+    let avatar = match avatar.filter(|avatar| !avatar.is_empty()) {
+        Some(avatar) => avatar,
+        None => {
+            let index = match discriminator {
+                "" | "0" => user_id.parse::<u64>().map(|id| (id >> 22) % 6),
+                _ => discriminator.parse::<u64>().map(|tag| tag % 5),
+            }
+            .unwrap_or(0);
+            return format!("{}/{}.png", DISCORD_DEFAULT_AVATAR_URL, index);
+        }
+    };
+    let ext = match avatar.starts_with("a_") {
         true => "gif",
         false => "png",
     };
-    format!(
-        "{}/{}/{}.{}",
-        DISCORD_AVATAR_URL,
-        user.id,
-        user.avatar.clone(),
-        ext
-    )
+    format!("{}/{}/{}.{}", DISCORD_AVATAR_URL, user_id, avatar, ext)
+}
+
+pub fn user_image_url(user: &db::User) -> String {
+    avatar_url(&user.id, &user.discriminator, user.avatar.as_deref())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -36,7 +53,8 @@ pub struct QuestionUser {
     // Fields from User
     pub user_id: String,
     pub username: String,
-    pub avatar: String,
+    pub avatar: Option<String>,
+    pub askee_username: String,
 
     // aditional fields
     pub answered: bool,
@@ -50,16 +68,79 @@ pub struct QuestionAnswered {
     pub body: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub public: bool,
+    pub askee_username: String,
     pub answered: bool,
     pub answer_body: Option<String>,
 }
 
-pub async fn app(
-    Extension(user): Extension<db::User>,
-    State(state): State<AppState>,
-) -> Result<AppTemplate> {
-    let questions = if user.is_admin {
-        state.db.get_unanswered_questions().await?
+#[derive(Debug, Serialize)]
+pub struct Quota {
+    pub limit: u64,
+    pub used: u64,
+    /// Left of the limit, bonus questions are counted separately
+    pub remaining: u64,
+    pub bonus: u64,
+    /// When more questions become available, in the user's timezone
+    pub resets_at: DateTime<Tz>,
+    /// Describes the policy's window, such as "today" or "this week"
+    pub period: String,
+    /// Set when questions do not all count as one
+    pub costs: Option<String>,
+}
+
+impl Quota {
+    pub fn can_ask(&self) -> bool {
+        self.remaining > 0 || self.bonus > 0
+    }
+}
+
+/// The user's own timezone, or the deployment's
+pub fn user_timezone(state: &AppState, user: &db::User) -> Tz {
+    user.timezone
+        .as_deref()
+        .and_then(parse_timezone)
+        .unwrap_or(state.config.quota_timezone)
+}
+
+/// The user's limit, including any boost an admin is running
+pub async fn daily_limit(state: &AppState, user: &db::User) -> Result<u64> {
+    let limit = state.db.get_daily_limit(user).await?;
+    let boost = state
+        .db
+        .get_active_limit_boosts(Utc::now())
+        .await?
+        .iter()
+        .fold(0, |sum: u64, boost| {
+            sum.saturating_add(boost.extra_questions)
+        });
+    Ok(limit.saturating_add(boost))
+}
+
+/// How many questions the user can still ask under the deployment's policy
+pub async fn quota(state: &AppState, user: &db::User) -> Result<Quota> {
+    let limit = daily_limit(state, user).await?;
+    let tz = user_timezone(state, user);
+    let usage = state
+        .config
+        .quota_policy
+        .usage(&state.db, user, limit, Utc::now(), &tz)
+        .await?;
+    Ok(Quota {
+        limit,
+        used: usage.used,
+        // The limit can be lowered after the user asked
+        remaining: limit.saturating_sub(usage.used),
+        bonus: user.bonus_questions,
+        resets_at: usage.frees_at,
+        period: state.config.quota_policy.period(limit),
+        costs: state.config.question_cost.describe(),
+    })
+}
+
+/// The askee's unanswered inbox, or the questions the user asked
+pub async fn list_questions(state: &AppState, user: &db::User) -> Result<Vec<QuestionUser>> {
+    let questions = if user.is_askee {
+        state.db.get_unanswered_questions(&user.id).await?
     } else {
         state
             .db
@@ -75,78 +156,195 @@ pub async fn app(
                 body: q.body.clone(),
                 created_at: q.created_at,
                 public: q.public,
+                askee_username: q.askee_username.clone(),
                 answered: q.answered,
                 answer_body: q.answer_body.clone(),
             })
             .collect()
     };
-    let daily_questions = state.db.get_user_daily_questions(&user.id).await?;
-
-    let remaining = user.daily_questions - daily_questions.len() as u64;
-    Ok(AppTemplate {
-        image_url: user_image_url(&user),
-        user_limit: user.daily_questions,
-        user,
-        q_count: questions.len(),
-        questions,
-        remaining,
-    })
+    Ok(questions)
 }
 
-pub async fn new_question(Extension(user): Extension<db::User>) -> Result<NewQuestionTemplate> {
-    Ok(NewQuestionTemplate {
-        image_url: user_image_url(&user),
-        user,
-    })
+pub async fn app(
+    Extension(user): Extension<db::User>,
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, AppTemplate)> {
+    let questions = list_questions(&state, &user).await?;
+    let quota = quota(&state, &user).await?;
+    let (jar, flash) = Flash::take(jar);
+
+    Ok((
+        jar,
+        AppTemplate {
+            image_url: user_image_url(&user),
+            user,
+            q_count: questions.len(),
+            questions,
+            quota,
+            flash,
+        },
+    ))
 }
 
 #[derive(Debug, Deserialize)]
-pub struct NewQuestionForm {
-    title: String,
-    body: Option<String>,
-    public: bool,
+pub struct NewQuestionQuery {
+    askee: Option<String>,
 }
 
-pub async fn submit_question(
-    State(state): State<AppState>,
+pub async fn new_question(
     Extension(user): Extension<db::User>,
-    Form(form): Form<NewQuestionForm>,
-) -> Result<Redirect> {
-    let body = form.body.unwrap_or_default();
-    let public = form.public;
-    let title = form.title;
+    State(state): State<AppState>,
+    Query(query): Query<NewQuestionQuery>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, NewQuestionTemplate)> {
+    let askees = state
+        .db
+        .get_askees()
+        .await?
+        .into_iter()
+        .filter(|askee| askee.id != user.id)
+        .collect::<Vec<_>>();
+    let (jar, flash) = Flash::take(jar);
+    let form = flash.as_ref().and_then(Flash::form::<NewQuestionForm>);
+    // Keep the askee of a turned down question, or preselect the one from a bot link, or the
+    // only one available
+    let selected_askee = match (form.as_ref(), query.askee) {
+        (Some(form), _) => Some(form.askee_id.clone()),
+        (None, Some(id)) => Some(id),
+        (None, None) if askees.len() == 1 => Some(askees[0].id.clone()),
+        (None, None) => None,
+    };
+
+    Ok((
+        jar,
+        NewQuestionTemplate {
+            image_url: user_image_url(&user),
+            user,
+            askees,
+            selected_askee,
+            flash,
+            form,
+        },
+    ))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewQuestionForm {
+    pub title: String,
+    pub body: Option<String>,
+    pub public: bool,
+    pub askee_id: String,
+}
 
-    if title.len() < 5 || title.len() > 100 {
+/// Lengths are counted in characters, like the `maxlength` of the form fields
+fn validate_question(title: &str, body: &str) -> Result<()> {
+    if !(5..=100).contains(&title.chars().count()) {
         return Err(Error::InvalidQuestionTitle);
     }
-
-    if body.len() > 1000 {
+    if body.chars().count() > 1000 {
         return Err(Error::InvalidQuestionBody);
     }
+    Ok(())
+}
+
+/// Validates and stores a question from `user`, then notifies the askee.
+/// Shared by the web form and the Discord `/ask` command.
+pub async fn ask_question(
+    state: &AppState,
+    user: &db::User,
+    form: NewQuestionForm,
+    channel_id: Option<String>,
+) -> Result<db::Question> {
+    if user.banned {
+        return Err(Error::Banned);
+    }
+
+    let body = form.body.unwrap_or_default();
+    let public = form.public;
+    let title = form.title;
 
-    let daily_limit = user.daily_questions;
+    validate_question(&title, &body)?;
 
-    let questions = state.db.get_user_daily_questions(&user.id).await?;
+    let askee = state
+        .db
+        .get_user(&form.askee_id)
+        .await?
+        .filter(|askee| askee.is_askee && askee.id != user.id)
+        .ok_or(Error::InvalidAskee)?;
 
-    if (questions.len() as u64) < daily_limit {
-        let question = db::Question {
-            id: uuid::Uuid::new_v4().to_string(),
-            title: title.clone(),
-            body,
-            public,
-            created_at: chrono::Utc::now(),
-            user_id: user.id.clone(),
-        };
-        state.db.create_question(question).await?;
-
-        let _ = send_sms(
-            &state.http,
-            &format!("Question submitted by {}: {}", user.username, title),
-        )
-        .await;
+    let limit = daily_limit(state, user).await?;
+    let tz = user_timezone(state, user);
+    let now = Utc::now();
+    let cost = state.config.question_cost.cost(public, &body);
+    let Some(allowance) = state
+        .config
+        .quota_policy
+        .spend(&state.db, user, limit, cost, now, &tz)
+        .await?
+    else {
+        let usage = state
+            .config
+            .quota_policy
+            .usage(&state.db, user, limit, now, &tz)
+            .await?;
+        return Err(Error::DailyLimitReached {
+            resets_at: usage.frees_at,
+        });
+    };
+
+    let question = db::Question {
+        id: uuid::Uuid::new_v4().to_string(),
+        title: title.clone(),
+        body,
+        public,
+        created_at: chrono::Utc::now(),
+        user_id: user.id.clone(),
+        askee_id: askee.id.clone(),
+        channel_id,
+        cost: allowance.counted_cost(cost),
+    };
+    if let Err(e) = state.db.create_question(question.clone()).await {
+        let refund = state
+            .config
+            .quota_policy
+            .refund(&state.db, user, limit, allowance, cost, now, &tz)
+            .await;
+        if let Err(e) = refund {
+            error!("Failed to refund {}'s question: {:?}", user.id, e);
+        }
+        return Err(e.into());
     }
 
-    Ok(Redirect::to("/app"))
+    state.notifier.dispatch(Notification {
+        recipient_id: askee.id.clone(),
+        subject: format!("New question for {}", askee.username),
+        body: format!(
+            "Question submitted by {} to {}: {}",
+            user.username, askee.username, title
+        ),
+    });
+
+    Ok(question)
+}
+
+/// Sends the user back to the form with what they typed when the question is turned down
+pub async fn submit_question(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+    Form(form): Form<NewQuestionForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    match ask_question(&state, &user, form.clone(), None).await {
+        Ok(question) => {
+            let flash = Flash::success(format!("Your question \"{}\" was sent", question.title));
+            Ok((flash.set(jar), Redirect::to("/app")))
+        }
+        Err(e) => {
+            let jar = Flash::from_error(e)?.with_form(&form).set(jar);
+            Ok((jar, Redirect::to("/app/question/new")))
+        }
+    }
 }
 
 #[derive(Template)]
@@ -155,53 +353,75 @@ pub struct AppAnswerTemplate {
     user: db::User,
     question: db::Question,
     image_url: String,
+    flash: Option<Flash>,
+    /// The answer that was turned down, if any
+    body: String,
 }
 
 pub async fn answer(
     Path(id): Path<String>,
     Extension(user): Extension<db::User>,
     State(state): State<AppState>,
-) -> Result<AppAnswerTemplate> {
-    if !user.is_admin {
-        return Err(Error::Unauthorized);
-    }
-
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, AppAnswerTemplate)> {
     let question = state
         .db
         .get_question(&id)
         .await?
         .ok_or(Error::QuestionNotFound)?;
 
-    Ok(AppAnswerTemplate {
-        question,
-        image_url: user_image_url(&user),
-        user,
-    })
+    if question.askee_id != user.id {
+        return Err(Error::Unauthorized);
+    }
+
+    let (jar, flash) = Flash::take(jar);
+    let body = flash
+        .as_ref()
+        .and_then(Flash::form::<NewAnswerForm>)
+        .map(|form| form.body)
+        .unwrap_or_default();
+
+    Ok((
+        jar,
+        AppAnswerTemplate {
+            question,
+            image_url: user_image_url(&user),
+            user,
+            flash,
+            body,
+        },
+    ))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewAnswerForm {
-    body: String,
+    pub body: String,
 }
 
-pub async fn submit_answer(
-    State(state): State<AppState>,
-    Path(id): Path<String>,
-    Extension(user): Extension<db::User>,
-    Form(form): Form<NewAnswerForm>,
-) -> Result<Redirect> {
+/// Validates and stores the askee's answer, then lets the asker know.
+/// Shared by the web form and the JSON API.
+pub async fn answer_question(
+    state: &AppState,
+    user: &db::User,
+    question_id: &str,
+    form: NewAnswerForm,
+) -> Result<db::Answer> {
     let body = form.body.trim().to_string();
 
-    if !user.is_admin {
-        return Err(Error::Unauthorized);
-    }
-
     let question = state
         .db
-        .get_question(&id)
+        .get_question(question_id)
         .await?
         .ok_or(Error::QuestionNotFound)?;
 
+    if question.askee_id != user.id {
+        return Err(Error::Unauthorized);
+    }
+
+    if !(5..=2000).contains(&body.chars().count()) {
+        return Err(Error::InvalidAnswerBody);
+    }
+
     let answer = state.db.get_question_answer(&question.id).await?;
 
     if answer.is_some() {
@@ -215,7 +435,211 @@ pub async fn submit_answer(
         user_id: user.id.clone(),
         question_id: question.id.clone(),
     };
-    state.db.create_answer(answer).await?;
+    // Two submits can race past the check above, the unique index settles it
+    state
+        .db
+        .create_answer(answer.clone())
+        .await
+        .map_err(|e| match e {
+            db::Error::Constraint(_) => Error::AnswerAlreadyExists,
+            e => e.into(),
+        })?;
+
+    let asker = state.db.get_user(&question.user_id).await?;
+    if let (Some(asker), Some(discord)) = (asker, state.discord.clone()) {
+        if asker.notify_answers {
+            let askee = user.clone();
+            let answer = answer.clone();
+            tokio::spawn(async move {
+                if let Err(e) =
+                    bot::notify_answered(&discord, &asker, &askee, &question, &answer).await
+                {
+                    error!("Failed to notify {} of their answer: {:?}", asker.id, e);
+                }
+            });
+        }
+    }
+
+    Ok(answer)
+}
+
+pub async fn submit_answer(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+    Form(form): Form<NewAnswerForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    match answer_question(&state, &user, &id, form.clone()).await {
+        Ok(_) => Ok((
+            Flash::success("Your answer was sent").set(jar),
+            Redirect::to("/app"),
+        )),
+        Err(e) => {
+            let jar = Flash::from_error(e)?.with_form(&form).set(jar);
+            Ok((jar, Redirect::to(&format!("/app/question/{}/answer", id))))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AskeeForm {
+    pub askee: bool,
+}
 
-    Ok(Redirect::to("/"))
+/// Lets an admin start or stop receiving questions
+pub async fn set_askee(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+    Form(form): Form<AskeeForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    if !user.is_admin {
+        return Err(Error::Unauthorized);
+    }
+
+    state.db.set_askee(&user.id, form.askee).await?;
+
+    let flash = match form.askee {
+        true => Flash::success("You are now accepting questions"),
+        false => Flash::success("You are no longer accepting questions"),
+    };
+    Ok((flash.set(jar), Redirect::to("/app")))
+}
+
+#[derive(Template)]
+#[template(path = "app/settings.html")]
+pub struct SettingsTemplate {
+    user: db::User,
+    image_url: String,
+    /// Used when the user has not picked a timezone
+    default_timezone: Tz,
+    timezones: &'static [Tz],
+    flash: Option<Flash>,
+}
+
+pub async fn settings(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, SettingsTemplate)> {
+    let (jar, flash) = Flash::take(jar);
+    Ok((
+        jar,
+        SettingsTemplate {
+            image_url: user_image_url(&user),
+            user,
+            default_timezone: state.config.quota_timezone,
+            timezones: &chrono_tz::TZ_VARIANTS,
+            flash,
+        },
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimezoneForm {
+    /// Empty to go back to the deployment's timezone
+    timezone: String,
+}
+
+pub async fn submit_timezone(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+    Form(form): Form<TimezoneForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    let timezone = match form.timezone.trim() {
+        "" => None,
+        name => match parse_timezone(name) {
+            Some(tz) => Some(tz),
+            None => {
+                let jar = Flash::from_error(Error::InvalidTimezone)?.set(jar);
+                return Ok((jar, Redirect::to("/app/settings")));
+            }
+        },
+    };
+
+    let now = Utc::now();
+    let old_today = quota_day(now, &user_timezone(&state, &user));
+    let new_today = quota_day(now, &timezone.unwrap_or(state.config.quota_timezone));
+    state
+        .db
+        .set_timezone(&user.id, timezone.map(|tz| tz.name()), old_today, new_today)
+        .await?;
+
+    let flash = Flash::success(format!(
+        "Your quota now resets at midnight {}",
+        timezone.unwrap_or(state.config.quota_timezone).name()
+    ));
+    Ok((flash.set(jar), Redirect::to("/app/settings")))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationsForm {
+    notify_answers: bool,
+}
+
+pub async fn submit_notifications(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+    Form(form): Form<NotificationsForm>,
+) -> Result<(SignedCookieJar, Redirect)> {
+    state
+        .db
+        .set_notify_answers(&user.id, form.notify_answers)
+        .await?;
+
+    let flash = Flash::success("Notification settings saved");
+    Ok((flash.set(jar), Redirect::to("/app/settings")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_question() {
+        assert!(validate_question("Hello", "").is_ok());
+        assert!(matches!(
+            validate_question("Hey", ""),
+            Err(Error::InvalidQuestionTitle)
+        ));
+        // Accented characters take two bytes but count once
+        assert!(validate_question(&"é".repeat(100), &"é".repeat(1000)).is_ok());
+        assert!(matches!(
+            validate_question(&"é".repeat(101), ""),
+            Err(Error::InvalidQuestionTitle)
+        ));
+        assert!(matches!(
+            validate_question("Hello", &"é".repeat(1001)),
+            Err(Error::InvalidQuestionBody)
+        ));
+    }
+
+    #[test]
+    fn test_avatar_url() {
+        assert_eq!(
+            avatar_url("80351110224678912", "0", Some("a_1234")),
+            "https://cdn.discordapp.com/avatars/80351110224678912/a_1234.gif"
+        );
+        assert_eq!(
+            avatar_url("80351110224678912", "0", Some("1234")),
+            "https://cdn.discordapp.com/avatars/80351110224678912/1234.png"
+        );
+        // (80351110224678912 >> 22) % 6 == 5
+        assert_eq!(
+            avatar_url("80351110224678912", "0", None),
+            "https://cdn.discordapp.com/embed/avatars/5.png"
+        );
+        // Legacy usernames go by the discriminator, 1337 % 5 == 2
+        assert_eq!(
+            avatar_url("80351110224678912", "1337", None),
+            "https://cdn.discordapp.com/embed/avatars/2.png"
+        );
+        assert_eq!(
+            avatar_url("80351110224678912", "0", Some("")),
+            avatar_url("80351110224678912", "0", None)
+        );
+    }
 }
@@ -1,77 +1,177 @@
 use crate::{
-    crypto::{self, get_key},
-    db,
+    auth::get_discord_user,
+    crypto, db,
     error::{Error, Result},
-    AppState, ADMIN_ID, COOKIE_NAME, GENERIC_DAILY_LIMIT,
+    AppState, COOKIE_NAME,
 };
 use axum::{
     extract::{Query, State},
     http::header::{HeaderMap, SET_COOKIE},
     response::{IntoResponse, Redirect},
 };
-use oauth2::{reqwest::async_http_client, AuthorizationCode, Scope, TokenResponse};
+use axum_extra::{
+    extract::cookie::{Cookie, PrivateCookieJar, SameSite},
+    headers::UserAgent,
+    TypedHeader,
+};
+use oauth2::{
+    reqwest::async_http_client,
+    url::{form_urlencoded, Url},
+    AuthorizationCode, CsrfToken, PkceCodeChallenge, PkceCodeVerifier, Scope, TokenResponse,
+};
 use serde::{Deserialize, Serialize};
 
+/// Carries the login attempt from `/auth/discord` to the callback
+const OAUTH_COOKIE: &str = "asklp_oauth";
+/// How long the user has to get through Discord's consent screen
+const OAUTH_TTL: chrono::Duration = chrono::Duration::minutes(10);
+
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
 pub struct AuthRequest {
     code: String,
     state: String,
 }
 
+/// What the callback needs to check the login attempt it receives is the one this browser
+/// started. Kept in an encrypted cookie so the PKCE verifier never shows up in the clear.
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginAttempt {
+    state: String,
+    pkce_verifier: String,
+    /// Where to go once signed in
+    next: Option<String>,
+    started_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginQuery {
+    next: Option<String>,
+}
+
+/// Only same-site paths are followed after login, anything else could send the user away.
+/// Browsers skip tabs and newlines in URLs, so "/\t/evil.example" would leave the site too.
+fn safe_next(next: &str) -> Option<&str> {
+    if !next.starts_with('/') || next.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return None;
+    }
+    let base = Url::parse("http://localhost").ok()?;
+    let local = base.join(next).ok()?.origin() == base.origin();
+    local.then_some(next)
+}
+
+/// Link to start logging in, coming back to `next` afterwards
+pub fn login_url(next: Option<&str>) -> String {
+    match next.and_then(safe_next) {
+        Some(next) => format!(
+            "/auth/discord?{}",
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("next", next)
+                .finish()
+        ),
+        None => "/auth/discord".to_string(),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
-struct DiscordUser {
-    id: String,
-    avatar: Option<String>,
-    username: String,
-    discriminator: String,
-    email: Option<String>,
+pub struct DiscordUser {
+    pub id: String,
+    pub avatar: Option<String>,
+    pub username: String,
+    pub discriminator: String,
+    pub email: Option<String>,
+}
+
+/// Returns the user matching the Discord profile, creating it on first sight and bringing its
+/// name and avatar up to date afterwards
+pub async fn get_or_create_user(state: &AppState, user_data: &DiscordUser) -> Result<db::User> {
+    if let Some(user) = state.db.get_user(&user_data.id).await? {
+        let changed = user.username != user_data.username
+            || user.discriminator != user_data.discriminator
+            || user.avatar != user_data.avatar;
+        if !changed {
+            return Ok(user);
+        }
+        state
+            .db
+            .update_profile(
+                &user.id,
+                &user_data.username,
+                &user_data.discriminator,
+                user_data.avatar.as_deref(),
+            )
+            .await?;
+        return Ok(db::User {
+            username: user_data.username.clone(),
+            discriminator: user_data.discriminator.clone(),
+            avatar: user_data.avatar.clone(),
+            ..user
+        });
+    }
+
+    let is_admin = state.config.admin_ids.contains(&user_data.id);
+    let user = db::User {
+        id: user_data.id.clone(),
+        username: user_data.username.clone(),
+        discriminator: user_data.discriminator.clone(),
+        avatar: user_data.avatar.clone(),
+        is_admin,
+        is_askee: is_admin,
+        notify_answers: true,
+        daily_limit: None,
+        joined_at: chrono::Utc::now(),
+        last_question_reset: None,
+        banned: false,
+        bonus_questions: 0,
+        questions_used: 0,
+        timezone: None,
+        quota_tat: None,
+    };
+    state.db.create_user(user.clone()).await?;
+    Ok(user)
 }
 
 pub async fn discord_cb(
     Query(query): Query<AuthRequest>,
     state: State<AppState>,
-) -> Result<impl IntoResponse> {
+    jar: PrivateCookieJar,
+    user_agent: Option<TypedHeader<UserAgent>>,
+) -> impl IntoResponse {
+    let attempt = jar
+        .get(OAUTH_COOKIE)
+        .and_then(|cookie| serde_json::from_str::<LoginAttempt>(cookie.value()).ok());
+    // The attempt is spent whether or not the login goes through, a retry starts a new one
+    let jar = jar.remove(Cookie::build(OAUTH_COOKIE).path("/"));
+    (jar, sign_in(&state, query, attempt, user_agent).await)
+}
+
+/// Finishes the login attempt, signing the user in with a new session
+async fn sign_in(
+    state: &AppState,
+    query: AuthRequest,
+    attempt: Option<LoginAttempt>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+) -> Result<(HeaderMap, Redirect)> {
+    let attempt = attempt.ok_or_else(|| Error::Auth("No login attempt in progress".to_string()))?;
+
+    if attempt.state != query.state {
+        return Err(Error::Auth("OAuth state does not match".to_string()));
+    }
+    if chrono::Utc::now() - attempt.started_at > OAUTH_TTL {
+        return Err(Error::Auth("Login attempt expired".to_string()));
+    }
+
     let token = state
         .oauth
         .exchange_code(AuthorizationCode::new(query.code.clone()))
+        .set_pkce_verifier(PkceCodeVerifier::new(attempt.pkce_verifier))
         .request_async(async_http_client)
         .await
         .map_err(|e| Error::Auth(e.to_string()))?;
-    let user_data: DiscordUser = state
-        .http
-        .get("https://discordapp.com/api/users/@me")
-        .bearer_auth(token.access_token().secret())
-        .send()
-        .await
-        .map_err(|e| Error::Auth(e.to_string()))?
-        .json::<DiscordUser>()
-        .await
-        .map_err(|e| Error::Auth(e.to_string()))?;
+    let user_data = get_discord_user(&state.http, token.access_token().secret()).await?;
 
-    let user = state.db.get_user(&user_data.id).await?;
-    if user.is_none() {
-        let is_admin = user_data.id == ADMIN_ID.to_string();
-        let user = db::User {
-            id: user_data.id.clone(),
-            username: user_data.username.clone(),
-            discriminator: user_data.discriminator.clone(),
-            avatar: user_data.avatar.unwrap(),
-            is_admin,
-            daily_questions: GENERIC_DAILY_LIMIT,
-            joined_at: chrono::Utc::now(),
-            last_question_reset: None,
-        };
-        state.db.create_user(user).await?;
-    }
+    get_or_create_user(state, &user_data).await?;
 
-    if let Some(existing_session) = state
-        .db
-        .get_active_session_by_user_id(&user_data.id)
-        .await?
-    {
-        state.db.delete_session(&existing_session.id).await?;
-    }
+    // Every device gets a session of its own, older ones stay signed in
     let session_id = uuid::Uuid::new_v4();
     let expires_in = token.expires_in().map(|d| d.as_secs()).unwrap_or(3600); // Default to 1 hour
     let expires_at = chrono::Utc::now() + chrono::Duration::seconds(expires_in as i64);
@@ -82,15 +182,14 @@ pub async fn discord_cb(
         max_age = expires_at.timestamp() - chrono::Utc::now().timestamp()
     );
 
-    let encryption_key = get_key();
+    let encryption_key = &state.config.encryption_key;
 
     let access_token_plain = token.access_token().secret();
-    let (access_token, access_token_nonce) = crypto::encrypt(&encryption_key, access_token_plain);
+    let (access_token, access_token_nonce) = crypto::encrypt(encryption_key, access_token_plain);
     assert_ne!(access_token, *access_token_plain);
 
     let refresh_token_plain = token.refresh_token().expect("refresh token").secret();
-    let (refresh_token, refresh_token_nonce) =
-        crypto::encrypt(&encryption_key, refresh_token_plain);
+    let (refresh_token, refresh_token_nonce) = crypto::encrypt(encryption_key, refresh_token_plain);
     assert_ne!(refresh_token, *refresh_token_plain);
 
     let session = db::Session {
@@ -101,23 +200,80 @@ pub async fn discord_cb(
         refresh_token: refresh_token.clone(),
         refresh_token_nonce: refresh_token_nonce.clone(),
         expires_at,
+        user_agent: user_agent.map(|TypedHeader(user_agent)| user_agent.to_string()),
+        created_at: Some(chrono::Utc::now()),
+        last_seen_at: Some(chrono::Utc::now()),
     };
     state.db.create_session(session).await?;
 
     let mut headers = HeaderMap::new();
     headers.insert(SET_COOKIE, cookie.parse().expect("failed to parse cookie"));
-    Ok((headers, Redirect::to("/app")))
+    let next = attempt
+        .next
+        .as_deref()
+        .and_then(safe_next)
+        .unwrap_or("/app");
+    Ok((headers, Redirect::to(next)))
 }
 
-pub async fn discord_auth(state: State<AppState>) -> impl IntoResponse {
-    let (auth_url, _csrf_token) = state
+pub async fn discord_auth(
+    state: State<AppState>,
+    Query(query): Query<LoginQuery>,
+    jar: PrivateCookieJar,
+) -> impl IntoResponse {
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    let (auth_url, csrf_token) = state
         .oauth
-        .authorize_url(oauth2::CsrfToken::new_random)
+        .authorize_url(CsrfToken::new_random)
         .add_scopes([
             Scope::new("identify".to_string()),
             Scope::new("email".to_string()),
         ])
+        .set_pkce_challenge(pkce_challenge)
         .url();
 
-    Redirect::to(auth_url.as_ref())
+    let attempt = LoginAttempt {
+        state: csrf_token.secret().clone(),
+        pkce_verifier: pkce_verifier.secret().clone(),
+        next: query.next.filter(|next| safe_next(next).is_some()),
+        started_at: chrono::Utc::now(),
+    };
+    let cookie = Cookie::build((
+        OAUTH_COOKIE,
+        serde_json::to_string(&attempt).expect("failed to serialize login attempt"),
+    ))
+    .path("/")
+    .http_only(true)
+    // Discord's redirect back is a top level navigation, which Lax cookies survive
+    .same_site(SameSite::Lax);
+
+    (jar.add(cookie), Redirect::to(auth_url.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_next() {
+        assert_eq!(
+            safe_next("/app/question/new?askee=1"),
+            Some("/app/question/new?askee=1")
+        );
+        assert_eq!(safe_next("//evil.example"), None);
+        assert_eq!(safe_next("/\\evil.example"), None);
+        assert_eq!(safe_next("https://evil.example"), None);
+        assert_eq!(safe_next("/\t/evil.example"), None);
+        assert_eq!(safe_next("/\n/evil.example"), None);
+        assert_eq!(safe_next("/ /evil.example"), None);
+        assert_eq!(safe_next("/\\/evil.example"), None);
+        assert_eq!(safe_next("app"), None);
+
+        assert_eq!(login_url(None), "/auth/discord");
+        assert_eq!(login_url(Some("//evil.example")), "/auth/discord");
+        assert_eq!(
+            login_url(Some("/app/question/new?askee=1")),
+            "/auth/discord?next=%2Fapp%2Fquestion%2Fnew%3Faskee%3D1"
+        );
+    }
 }
@@ -0,0 +1,249 @@
+use crate::{notify::Dispatcher, AppState};
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use serenity::gateway::ConnectionStage;
+
+/// How long the database gets to answer before it counts as unreachable
+const DATABASE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Past this the replica serves stale data. It syncs every minute.
+fn max_sync_age() -> Duration {
+    Duration::minutes(5)
+}
+
+/// Ordered from best to worst, the readiness is the worst of its checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Not relevant to this process, e.g. the bot when running `asklp serve`
+    Skipped,
+    /// Worth looking into but the web app still works
+    Degraded,
+    /// The machine should not get traffic
+    Failing,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Checks {
+    pub database: Check,
+    pub replica_sync: Check,
+    pub discord: Check,
+    pub notifier: Check,
+}
+
+impl Checks {
+    fn status(&self) -> Status {
+        let worst = [
+            &self.database,
+            &self.replica_sync,
+            &self.discord,
+            &self.notifier,
+        ]
+        .iter()
+        .map(|check| check.status)
+        .max()
+        .unwrap_or(Status::Ok);
+        match worst {
+            Status::Skipped => Status::Ok,
+            worst => worst,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub status: Status,
+    pub checks: Checks,
+}
+
+/// Liveness, answers as long as the process serves requests
+pub async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness, with a breakdown of every dependency. Responds 503 when the database is
+/// unreachable or the replica is stale. A disconnected bot or a failing notifier only degrade
+/// it, the web app works without them.
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let checks = Checks {
+        database: database(&state).await,
+        replica_sync: replica_sync(
+            state.database.is_replica(),
+            state.database.last_synced_at(),
+            Utc::now(),
+        ),
+        discord: discord(&state).await,
+        notifier: notifier(&state.notifier),
+    };
+    let status = checks.status();
+    let code = match status {
+        Status::Failing => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
+    (code, Json(Readiness { status, checks }))
+}
+
+async fn database(state: &AppState) -> Check {
+    match tokio::time::timeout(DATABASE_TIMEOUT, state.db.ping()).await {
+        Ok(Ok(())) => Check::new(Status::Ok, "Answered a query"),
+        Ok(Err(e)) => Check::new(Status::Failing, e.to_string()),
+        Err(_) => Check::new(
+            Status::Failing,
+            format!("No answer after {}s", DATABASE_TIMEOUT.as_secs()),
+        ),
+    }
+}
+
+fn replica_sync(replica: bool, last_synced_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Check {
+    if !replica {
+        return Check::new(Status::Skipped, "Not a replica");
+    }
+    match last_synced_at {
+        Some(at) if now - at <= max_sync_age() => Check::new(
+            Status::Ok,
+            format!("Synced {}s ago", (now - at).num_seconds()),
+        ),
+        Some(at) => Check::new(
+            Status::Failing,
+            format!("Last synced {}s ago", (now - at).num_seconds()),
+        ),
+        None => Check::new(Status::Failing, "Never synced"),
+    }
+}
+
+async fn discord(state: &AppState) -> Check {
+    match state.shards.get() {
+        Some(shards) => {
+            let stages = shards
+                .runners
+                .lock()
+                .await
+                .values()
+                .map(|runner| runner.stage)
+                .collect::<Vec<_>>();
+            shards_check(&stages)
+        }
+        None => Check::new(Status::Skipped, "The bot does not run in this process"),
+    }
+}
+
+fn shards_check(stages: &[ConnectionStage]) -> Check {
+    if stages.is_empty() {
+        return Check::new(Status::Degraded, "No shard started yet");
+    }
+    let connected = stages
+        .iter()
+        .filter(|stage| **stage == ConnectionStage::Connected)
+        .count();
+    let status = if connected < stages.len() {
+        Status::Degraded
+    } else {
+        Status::Ok
+    };
+    let others = stages
+        .iter()
+        .filter(|stage| **stage != ConnectionStage::Connected)
+        .map(|stage| stage.to_string())
+        .collect::<Vec<_>>();
+    let mut detail = format!("{}/{} shards connected", connected, stages.len());
+    if !others.is_empty() {
+        detail = format!("{}, others {}", detail, others.join(", "));
+    }
+    Check::new(status, detail)
+}
+
+fn notifier(dispatcher: &Dispatcher) -> Check {
+    let names = dispatcher.names();
+    if names.is_empty() {
+        return Check::new(Status::Skipped, "No notifiers configured");
+    }
+    match dispatcher.failed_count() {
+        0 => Check::new(Status::Ok, names.join(", ")),
+        waiting => Check::new(
+            Status::Degraded,
+            format!(
+                "{}, {} notifications waiting for a retry",
+                names.join(", "),
+                waiting
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(status: Status) -> Check {
+        Check::new(status, "")
+    }
+
+    #[test]
+    fn test_status_is_the_worst_check() {
+        let mut checks = Checks {
+            database: check(Status::Ok),
+            replica_sync: check(Status::Skipped),
+            discord: check(Status::Skipped),
+            notifier: check(Status::Skipped),
+        };
+        assert_eq!(checks.status(), Status::Ok);
+
+        checks.notifier = check(Status::Degraded);
+        assert_eq!(checks.status(), Status::Degraded);
+
+        checks.replica_sync = check(Status::Failing);
+        assert_eq!(checks.status(), Status::Failing);
+    }
+
+    #[test]
+    fn test_replica_sync() {
+        let now = Utc::now();
+        assert_eq!(replica_sync(false, None, now).status, Status::Skipped);
+        assert_eq!(replica_sync(true, None, now).status, Status::Failing);
+
+        let recent = replica_sync(true, Some(now - Duration::seconds(30)), now);
+        assert_eq!(recent.status, Status::Ok);
+        assert_eq!(recent.detail, "Synced 30s ago");
+
+        let stale = replica_sync(true, Some(now - Duration::minutes(10)), now);
+        assert_eq!(stale.status, Status::Failing);
+        assert_eq!(stale.detail, "Last synced 600s ago");
+    }
+
+    #[test]
+    fn test_shards() {
+        let connected = shards_check(&[ConnectionStage::Connected]);
+        assert_eq!(connected.status, Status::Ok);
+        assert_eq!(connected.detail, "1/1 shards connected");
+
+        let resuming = shards_check(&[ConnectionStage::Resuming]);
+        assert_eq!(resuming.status, Status::Degraded);
+        assert_eq!(resuming.detail, "0/1 shards connected, others resuming");
+
+        assert_eq!(shards_check(&[]).status, Status::Degraded);
+    }
+
+    #[test]
+    fn test_notifier() {
+        let check = notifier(&Dispatcher::new(Vec::new()));
+        assert_eq!(check.status, Status::Skipped);
+    }
+}
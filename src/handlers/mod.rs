@@ -7,9 +7,14 @@ use axum::{
 };
 use axum_extra::{extract::TypedHeader, headers::Cookie};
 
+pub mod admin;
+pub mod api;
 pub mod app;
 pub mod discord;
+pub mod health;
 pub mod questions;
+pub mod sessions;
+pub mod tokens;
 
 pub async fn logout(
     TypedHeader(cookies): TypedHeader<Cookie>,
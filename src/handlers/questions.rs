@@ -1,22 +1,108 @@
-// use crate::{db, error::Result, AppState};
-// use axum::extract::State;
+use crate::{db, error::Result, AppState};
+use askama::Template;
+use axum::extract::{Extension, Query, State};
+use oauth2::url::form_urlencoded;
+use serde::{Deserialize, Serialize};
 
-struct QueryString {
+use super::app::avatar_url;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct QueryString {
     user_id: Option<String>,
     answered: Option<bool>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct PublicQuestionSearch {
-    id: String,
-    title: String,
-    body: String,
-    created_at: chrono::DateTime<chrono::Utc>,
-    user_id: String,
-    username: String,
-    answered: bool,
+    pub id: String,
+    pub title: String,
+    pub body: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub user_id: String,
+    pub username: String,
+    pub discriminator: String,
+    pub avatar: Option<String>,
+    pub askee_username: String,
+    pub answered: bool,
+    pub answer_body: Option<String>,
+}
+
+impl PublicQuestionSearch {
+    pub fn image_url(&self) -> String {
+        avatar_url(&self.user_id, &self.discriminator, self.avatar.as_deref())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "questions.html")]
+pub struct PublicQuestionsTemplate {
+    user: Option<db::User>,
+    questions: Vec<PublicQuestionSearch>,
+    user_id: Option<String>,
+    answered: Option<bool>,
+}
+
+impl PublicQuestionsTemplate {
+    /// Builds a feed link that keeps the current user filter and sets the answered filter
+    fn filter_url(&self, answered: Option<bool>) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        if let Some(user_id) = &self.user_id {
+            query.append_pair("user_id", user_id);
+        }
+        if let Some(answered) = answered {
+            query.append_pair("answered", &answered.to_string());
+        }
+        let query = query.finish();
+        if query.is_empty() {
+            "/questions".to_string()
+        } else {
+            format!("/questions?{}", query)
+        }
+    }
+
+    fn is_filtered(&self) -> bool {
+        self.user_id.is_some() || self.answered.is_some()
+    }
+}
+
+pub async fn get_public_questions(
+    State(state): State<AppState>,
+    user: Option<Extension<db::User>>,
+    Query(query): Query<QueryString>,
+) -> Result<PublicQuestionsTemplate> {
+    let questions = state
+        .db
+        .get_public_questions(query.user_id.as_deref(), query.answered)
+        .await?;
+
+    Ok(PublicQuestionsTemplate {
+        user: user.map(|Extension(user)| user),
+        questions,
+        user_id: query.user_id,
+        answered: query.answered,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_url() {
+        let mut template = PublicQuestionsTemplate {
+            user: None,
+            questions: Vec::new(),
+            user_id: None,
+            answered: None,
+        };
+        assert_eq!(template.filter_url(None), "/questions");
+        assert!(!template.is_filtered());
+
+        template.user_id = Some("1&answered=false#top".to_string());
+        assert_eq!(
+            template.filter_url(Some(true)),
+            "/questions?user_id=1%26answered%3Dfalse%23top&answered=true"
+        );
+        assert!(template.is_filtered());
+    }
 }
-// pub async fn get_public_questions(
-//     State(state): State<AppState>,
-//     ) -> Result<PublicQuestionsTemplate> {
-//
-// }
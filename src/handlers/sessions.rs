@@ -0,0 +1,158 @@
+use crate::{
+    db,
+    error::{Error, Result},
+    flash::Flash,
+    mw::CurrentSession,
+    AppState,
+};
+use askama::Template;
+use axum::{
+    extract::{Extension, Path, State},
+    response::Redirect,
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+
+use super::app::user_image_url;
+
+#[derive(Template)]
+#[template(path = "app/sessions.html")]
+pub struct SessionsTemplate {
+    user: db::User,
+    image_url: String,
+    sessions: Vec<db::Session>,
+    current_session: String,
+    flash: Option<Flash>,
+}
+
+impl SessionsTemplate {
+    fn device(&self, session: &db::Session) -> String {
+        session
+            .user_agent
+            .as_deref()
+            .map(describe_user_agent)
+            .unwrap_or_else(|| "Unknown device".to_string())
+    }
+}
+
+/// Names the browser and operating system of a user agent, like "Firefox on Windows"
+fn describe_user_agent(user_agent: &str) -> String {
+    // Order matters, most user agents also claim to be the browsers they descend from
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map(|(_, name)| name);
+    let os = [
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("CrOS", "ChromeOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map(|(_, name)| name);
+
+    match (browser, os) {
+        (Some(browser), Some(os)) => format!("{} on {}", browser, os),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        (None, None) => "Unknown device".to_string(),
+    }
+}
+
+pub async fn sessions(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Extension(CurrentSession(current_session)): Extension<CurrentSession>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, SessionsTemplate)> {
+    let sessions = state.db.get_sessions_by_user_id(&user.id).await?;
+    let (jar, flash) = Flash::take(jar);
+
+    Ok((
+        jar,
+        SessionsTemplate {
+            image_url: user_image_url(&user),
+            user,
+            sessions,
+            current_session,
+            flash,
+        },
+    ))
+}
+
+/// Signs one of the user's devices out. Revoking the current session is the same as logging out.
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Extension(CurrentSession(current_session)): Extension<CurrentSession>,
+    Path(id): Path<String>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, Redirect)> {
+    let session = state
+        .db
+        .get_session(&id)
+        .await?
+        .filter(|session| session.user_id == user.id)
+        .ok_or(Error::NotFound)?;
+    if session.id == current_session {
+        return Ok((jar, Redirect::to("/logout")));
+    }
+    state.db.delete_session(&session.id).await?;
+
+    let flash = Flash::success("Session revoked");
+    Ok((flash.set(jar), Redirect::to("/app/sessions")))
+}
+
+pub async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Extension(CurrentSession(current_session)): Extension<CurrentSession>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, Redirect)> {
+    let revoked = state
+        .db
+        .delete_other_sessions(&user.id, &current_session)
+        .await?;
+
+    let flash = match revoked {
+        1 => Flash::success("Signed out of 1 other session"),
+        n => Flash::success(format!("Signed out of {} other sessions", n)),
+    };
+    Ok((flash.set(jar), Redirect::to("/app/sessions")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_user_agent() {
+        assert_eq!(
+            describe_user_agent(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:131.0) Gecko/20100101 Firefox/131.0"
+            ),
+            "Firefox on Windows"
+        );
+        assert_eq!(
+            describe_user_agent("Mozilla/5.0 (iPhone; CPU iPhone OS 17_6 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.6 Mobile/15E148 Safari/604.1"),
+            "Safari on iOS"
+        );
+        assert_eq!(
+            describe_user_agent("Mozilla/5.0 (Linux; Android 10; K) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Mobile Safari/537.36"),
+            "Chrome on Android"
+        );
+        assert_eq!(
+            describe_user_agent("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36 Edg/130.0.0.0"),
+            "Edge on macOS"
+        );
+        assert_eq!(describe_user_agent("curl/8.5.0"), "Unknown device");
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{
+    auth::{Scope, Scopes},
+    crypto::{generate_token, hash_token},
+    db,
+    error::{Error, Result},
+    flash::Flash,
+    AppState,
+};
+use askama::Template;
+use axum::{
+    extract::{Extension, Form, Path, State},
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+use serde::{Deserialize, Serialize};
+
+use super::app::user_image_url;
+
+#[derive(Template)]
+#[template(path = "app/tokens.html")]
+pub struct TokensTemplate {
+    user: db::User,
+    image_url: String,
+    tokens: Vec<db::ApiToken>,
+    /// The secret of a freshly minted token, only ever shown once
+    new_token: Option<String>,
+    flash: Option<Flash>,
+    /// The name of a token that was turned down
+    name: String,
+}
+
+async fn render(
+    state: &AppState,
+    user: db::User,
+    new_token: Option<String>,
+    flash: Option<Flash>,
+) -> Result<TokensTemplate> {
+    let tokens = state.db.get_api_tokens_by_user_id(&user.id).await?;
+    let name = flash
+        .as_ref()
+        .and_then(Flash::form::<NewTokenForm>)
+        .map(|form| form.name)
+        .unwrap_or_default();
+    Ok(TokensTemplate {
+        image_url: user_image_url(&user),
+        user,
+        tokens,
+        new_token,
+        flash,
+        name,
+    })
+}
+
+pub async fn tokens(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, TokensTemplate)> {
+    let (jar, flash) = Flash::take(jar);
+    Ok((jar, render(&state, user, None, flash).await?))
+}
+
+/// Checkboxes are only sent when ticked
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewTokenForm {
+    name: String,
+    read: Option<String>,
+    ask: Option<String>,
+    answer: Option<String>,
+}
+
+pub async fn create_token(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    jar: SignedCookieJar,
+    Form(form): Form<NewTokenForm>,
+) -> Result<Response> {
+    let name = form.name.trim().to_string();
+    let scopes = [
+        (&form.read, Scope::Read),
+        (&form.ask, Scope::Ask),
+        (&form.answer, Scope::Answer),
+    ]
+    .into_iter()
+    .filter_map(|(checked, scope)| checked.as_ref().map(|_| scope))
+    .collect::<Vec<_>>();
+    let scopes = Scopes::new(scopes);
+
+    if name.is_empty() || name.chars().count() > 50 || scopes.is_empty() {
+        let flash = Flash::from_error(Error::InvalidTokenName)?.with_form(&form);
+        return Ok((flash.set(jar), Redirect::to("/app/tokens")).into_response());
+    }
+
+    let token = generate_token();
+    state
+        .db
+        .create_api_token(db::ApiToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user.id.clone(),
+            name,
+            token_hash: hash_token(&token),
+            scopes: scopes.to_string(),
+            created_at: chrono::Utc::now(),
+            last_used_at: None,
+        })
+        .await?;
+
+    // Rendered rather than redirected to, the secret must not outlive this response
+    Ok(render(&state, user, Some(token), None)
+        .await?
+        .into_response())
+}
+
+pub async fn revoke_token(
+    State(state): State<AppState>,
+    Extension(user): Extension<db::User>,
+    Path(id): Path<String>,
+    jar: SignedCookieJar,
+) -> Result<(SignedCookieJar, Redirect)> {
+    if !state.db.delete_api_token(&id, &user.id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let flash = Flash::success("Token revoked");
+    Ok((flash.set(jar), Redirect::to("/app/tokens")))
+}
@@ -0,0 +1,426 @@
+use crate::{
+    db, error::Result, handlers::app::user_timezone, notify::Notification, shutdown::Shutdown,
+    time::quota_day, AppState, SITE_URL,
+};
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, info_span, Instrument};
+
+/// When a job runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every so often, starting one interval after boot
+    Every(Duration),
+    /// Once a day at this time in the deployment's quota timezone
+    DailyAt(NaiveTime),
+}
+
+impl Schedule {
+    pub fn next_run(&self, now: DateTime<Utc>, tz: &Tz) -> DateTime<Utc> {
+        match self {
+            Schedule::Every(interval) => now + *interval,
+            Schedule::DailyAt(time) => {
+                let mut day = now.with_timezone(tz).date_naive();
+                loop {
+                    // A time skipped by a DST change runs an hour later that day
+                    let local = day.and_time(*time);
+                    let at = tz.from_local_datetime(&local).earliest().or_else(|| {
+                        tz.from_local_datetime(&(local + Duration::hours(1)))
+                            .earliest()
+                    });
+                    match at.map(|at| at.with_timezone(&Utc)) {
+                        Some(at) if at > now => return at,
+                        _ => day = day.succ_opt().expect("date out of range"),
+                    }
+                }
+            }
+        }
+    }
+
+    /// How long a run claimed by one process keeps the others from running the job. Half the
+    /// schedule, so schedulers booted at different times still run it about as often.
+    pub fn lease(&self) -> Duration {
+        match self {
+            Schedule::Every(interval) => *interval / 2,
+            Schedule::DailyAt(_) => Duration::hours(12),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Schedule::Every(interval) if interval.num_seconds() % 3600 == 0 => {
+                format!("every {}h", interval.num_hours())
+            }
+            Schedule::Every(interval) if interval.num_seconds() % 60 == 0 => {
+                format!("every {}m", interval.num_minutes())
+            }
+            Schedule::Every(interval) => format!("every {}s", interval.num_seconds()),
+            Schedule::DailyAt(time) => format!("daily at {}", time.format("%H:%M")),
+        }
+    }
+}
+
+type Run = fn(AppState) -> BoxFuture<'static, Result<String>>;
+
+/// A housekeeping task run in the background, returning a short summary of what it did
+pub struct Job {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub schedule: Schedule,
+    /// Also runs in bot-only processes, for state each process keeps on its own. Other jobs
+    /// only run next to the web app, and only in the process that claims each run in the
+    /// database, so they are not doubled up across machines.
+    pub per_process: bool,
+    run: Run,
+}
+
+/// Every job the scheduler runs
+pub fn jobs() -> Vec<Job> {
+    vec![
+        Job {
+            name: "session_cleanup",
+            description: "Deletes expired sessions",
+            schedule: Schedule::Every(Duration::hours(1)),
+            per_process: false,
+            run: |state| Box::pin(clean_up_sessions(state)),
+        },
+        Job {
+            name: "quota_resets",
+            description: "Zeroes daily question counters left over from previous days",
+            schedule: Schedule::Every(Duration::minutes(15)),
+            per_process: false,
+            run: |state| Box::pin(reset_quotas(state)),
+        },
+        Job {
+            name: "notification_retries",
+            description: "Retries notifications that ran out of attempts",
+            schedule: Schedule::Every(Duration::minutes(1)),
+            per_process: true,
+            run: |state| Box::pin(retry_notifications(state)),
+        },
+        Job {
+            name: "digest_emails",
+            description: "Emails askees a summary of their unanswered questions",
+            schedule: Schedule::DailyAt(NaiveTime::from_hms_opt(9, 0, 0).unwrap()),
+            per_process: false,
+            run: |state| Box::pin(send_digests(state)),
+        },
+        Job {
+            name: "database_sync",
+            description: "Pulls the latest changes into the embedded replica",
+            schedule: Schedule::Every(Duration::minutes(1)),
+            per_process: true,
+            run: |state| Box::pin(sync_database(state)),
+        },
+    ]
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum Outcome {
+    Succeeded(String),
+    Failed(String),
+}
+
+/// What the admin jobs page shows
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub schedule: String,
+    pub running: bool,
+    pub runs: u64,
+    pub failures: u64,
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub last_duration_ms: Option<i64>,
+    pub last_outcome: Option<Outcome>,
+    pub next_run_at: Option<DateTime<Utc>>,
+}
+
+impl JobStatus {
+    pub fn last_failed(&self) -> bool {
+        matches!(self.last_outcome, Some(Outcome::Failed(_)))
+    }
+}
+
+/// Status of every scheduled job, shared with the admin pages
+#[derive(Clone, Default)]
+pub struct Jobs {
+    statuses: Arc<RwLock<Vec<JobStatus>>>,
+}
+
+impl Jobs {
+    pub fn statuses(&self) -> Vec<JobStatus> {
+        self.statuses.read().unwrap().clone()
+    }
+
+    fn register(&self, job: &Job) {
+        self.statuses.write().unwrap().push(JobStatus {
+            name: job.name,
+            description: job.description,
+            schedule: job.schedule.describe(),
+            running: false,
+            runs: 0,
+            failures: 0,
+            last_started_at: None,
+            last_duration_ms: None,
+            last_outcome: None,
+            next_run_at: None,
+        });
+    }
+
+    fn update(&self, name: &str, update: impl FnOnce(&mut JobStatus)) {
+        let mut statuses = self.statuses.write().unwrap();
+        if let Some(status) = statuses.iter_mut().find(|status| status.name == name) {
+            update(status);
+        }
+    }
+}
+
+/// Runs the job once and records how it went
+async fn run_once(jobs: &Jobs, job: &Job, state: AppState) {
+    let started_at = Utc::now();
+    jobs.update(job.name, |status| {
+        status.running = true;
+        status.last_started_at = Some(started_at);
+    });
+
+    let result = (job.run)(state).await;
+    let duration = Utc::now() - started_at;
+    let outcome = match result {
+        Ok(summary) => {
+            info!(
+                "Job finished in {}ms: {}",
+                duration.num_milliseconds(),
+                summary
+            );
+            Outcome::Succeeded(summary)
+        }
+        Err(e) => {
+            error!(
+                "Job failed after {}ms: {:?}",
+                duration.num_milliseconds(),
+                e
+            );
+            Outcome::Failed(format!("{:?}", e))
+        }
+    };
+
+    jobs.update(job.name, |status| {
+        status.running = false;
+        status.runs += 1;
+        if matches!(outcome, Outcome::Failed(_)) {
+            status.failures += 1;
+        }
+        status.last_duration_ms = Some(duration.num_milliseconds());
+        status.last_outcome = Some(outcome);
+    });
+}
+
+/// Starts every job this process runs on its schedule in the background, until shutdown
+pub fn start(state: AppState, shutdown: Shutdown) {
+    let runs_server = state.config.runs_server();
+    for job in jobs()
+        .into_iter()
+        .filter(|job| job.per_process || runs_server)
+    {
+        state.jobs.register(&job);
+        let state = state.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            loop {
+                let now = Utc::now();
+                let next = job.schedule.next_run(now, &state.config.quota_timezone);
+                state
+                    .jobs
+                    .update(job.name, |status| status.next_run_at = Some(next));
+                tokio::select! {
+                    _ = tokio::time::sleep((next - now).to_std().unwrap_or_default()) => {}
+                    _ = shutdown.requested() => return,
+                }
+
+                let span = info_span!("job", name = job.name);
+                match claim(&state.db, &job, Utc::now()).await {
+                    Ok(true) => {
+                        run_once(&state.jobs, &job, state.clone())
+                            .instrument(span)
+                            .await
+                    }
+                    Ok(false) => span.in_scope(|| info!("Skipped, another process ran it")),
+                    Err(e) => span.in_scope(|| error!("Failed to claim the run: {:?}", e)),
+                }
+            }
+        });
+    }
+    info!("Started {} background jobs", state.jobs.statuses().len());
+}
+
+/// Whether this process should run the job now. Shared jobs go to the first process that
+/// claims them.
+async fn claim(db: &db::Model, job: &Job, now: DateTime<Utc>) -> Result<bool> {
+    if job.per_process {
+        return Ok(true);
+    }
+    Ok(db
+        .claim_job_run(job.name, now, job.schedule.lease())
+        .await?)
+}
+
+async fn clean_up_sessions(state: AppState) -> Result<String> {
+    let deleted = state.db.clean_up_expired_sessions().await?;
+    Ok(format!("Deleted {} expired sessions", deleted))
+}
+
+async fn reset_quotas(state: AppState) -> Result<String> {
+    let now = Utc::now();
+    let mut reset = 0;
+    for user in state.db.get_users_with_questions_used().await? {
+        let today = quota_day(now, &user_timezone(&state, &user));
+        if state.db.reset_questions_used(&user.id, today).await? {
+            reset += 1;
+        }
+    }
+    Ok(format!("Reset {} daily counters", reset))
+}
+
+async fn retry_notifications(state: AppState) -> Result<String> {
+    let retried = state.notifier.retry_failed().await;
+    Ok(format!(
+        "Sent {}, requeued {}, dropped {}",
+        retried.sent, retried.requeued, retried.dropped
+    ))
+}
+
+async fn send_digests(state: AppState) -> Result<String> {
+    if !state.notifier.names().contains(&"email") {
+        return Ok("Skipped, the email notifier is not configured".to_string());
+    }
+
+    let mut sent = 0;
+    for askee in state.db.get_askees().await? {
+        let questions = state.db.get_unanswered_questions(&askee.id).await?;
+        if questions.is_empty() {
+            continue;
+        }
+        let titles = questions
+            .iter()
+            .map(|question| format!("- {} (from {})", question.title, question.username))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let notification = Notification {
+            recipient_id: askee.id.clone(),
+            subject: format!(
+                "{} unanswered questions for {}",
+                questions.len(),
+                askee.username
+            ),
+            body: format!("{}\n\nAnswer them at {}/app", titles, SITE_URL),
+        };
+        // Failures end up with the notification retries
+        state.notifier.dispatch_to("email", notification);
+        sent += 1;
+    }
+    Ok(format!("Queued {} digests", sent))
+}
+
+async fn sync_database(state: AppState) -> Result<String> {
+    state.database.sync().await?;
+    Ok("Synced".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America;
+
+    #[test]
+    fn test_next_run() {
+        let tz = America::New_York;
+        let now = tz.with_ymd_and_hms(2024, 11, 15, 8, 0, 0).unwrap().to_utc();
+        let nine = Schedule::DailyAt(NaiveTime::from_hms_opt(9, 0, 0).unwrap());
+
+        assert_eq!(
+            Schedule::Every(Duration::minutes(15)).next_run(now, &tz),
+            now + Duration::minutes(15)
+        );
+        assert_eq!(
+            nine.next_run(now, &tz),
+            tz.with_ymd_and_hms(2024, 11, 15, 9, 0, 0).unwrap()
+        );
+        // Right at 9 the next run is tomorrow's
+        let at_nine = tz.with_ymd_and_hms(2024, 11, 15, 9, 0, 0).unwrap().to_utc();
+        assert_eq!(
+            nine.next_run(at_nine, &tz),
+            tz.with_ymd_and_hms(2024, 11, 16, 9, 0, 0).unwrap()
+        );
+
+        // 2:30 does not exist on the day clocks spring forward
+        let half_two = Schedule::DailyAt(NaiveTime::from_hms_opt(2, 30, 0).unwrap());
+        let before = tz.with_ymd_and_hms(2024, 3, 10, 0, 0, 0).unwrap().to_utc();
+        assert_eq!(
+            half_two.next_run(before, &tz),
+            tz.with_ymd_and_hms(2024, 3, 10, 3, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_describe_schedule() {
+        assert_eq!(Schedule::Every(Duration::hours(1)).describe(), "every 1h");
+        assert_eq!(
+            Schedule::Every(Duration::minutes(15)).describe(),
+            "every 15m"
+        );
+        assert_eq!(
+            Schedule::Every(Duration::seconds(30)).describe(),
+            "every 30s"
+        );
+        assert_eq!(
+            Schedule::DailyAt(NaiveTime::from_hms_opt(9, 0, 0).unwrap()).describe(),
+            "daily at 09:00"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shared_jobs_run_once_across_processes() {
+        // Two processes on the same database file
+        let path = std::env::temp_dir().join(format!("asklp-jobs-{}.db", uuid::Uuid::new_v4()));
+        let location = db::Location::Local(path.to_string_lossy().into_owned());
+        let mut schedulers = Vec::new();
+        for _ in 0..2 {
+            let database = db::Database::open(&location).await.unwrap();
+            schedulers.push(database.model().await.unwrap());
+        }
+
+        let jobs = jobs();
+        let digests = jobs.iter().find(|job| job.name == "digest_emails").unwrap();
+        let sync = jobs.iter().find(|job| job.name == "database_sync").unwrap();
+        let now = Utc::now();
+        let mut runs = 0;
+        for db in &schedulers {
+            if claim(db, digests, now).await.unwrap() {
+                runs += 1;
+            }
+            assert!(claim(db, sync, now).await.unwrap());
+        }
+        assert_eq!(runs, 1);
+
+        // The next day's run is up for grabs again
+        let tomorrow = now + Duration::days(1);
+        assert!(claim(&schedulers[1], digests, tomorrow).await.unwrap());
+        assert!(!claim(&schedulers[0], digests, tomorrow).await.unwrap());
+
+        drop(schedulers);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_jobs_have_unique_names() {
+        let jobs = jobs();
+        let mut names = jobs.iter().map(|job| job.name).collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), jobs.len());
+    }
+}
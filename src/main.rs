@@ -1,45 +1,71 @@
 #![allow(dead_code)]
 use askama::Template;
-use axum::{extract::State, middleware, response::IntoResponse, routing, Json, Router};
-use bot::Handler;
+use axum::{
+    extract::{FromRef, Query},
+    middleware, routing, Router,
+};
+use axum_extra::extract::cookie::Key;
 use error::Result;
 use governor::Quota;
 use handlers::app::QuestionUser;
-use libsql::Builder;
 use nonzero_ext::nonzero;
 use oauth2::basic::BasicClient;
-use serenity::all::{ActivityData, OnlineStatus};
-use serenity::{all::GatewayIntents, Client};
+use serenity::all::ShardManager;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::sync::{Arc, OnceLock};
 use tokio::net::TcpListener;
 use tower_http::compression::CompressionLayer;
 use tower_http::services::ServeDir;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use self::mw::RateLimiterLayer;
 
 mod auth;
 mod bot;
+mod config;
 mod crypto;
 mod db;
 mod error;
+mod flash;
 mod handlers;
+mod jobs;
+mod migrations;
 mod mw;
+mod notify;
 mod oai;
+mod quota;
+mod shutdown;
 mod time;
-mod twilio;
 
 pub const COOKIE_NAME: &str = "asklp_session";
-pub const ADMIN_ID: u64 = 173963703606181888;
 pub const DISCORD_AVATAR_URL: &str = "https://cdn.discordapp.com/avatars";
+pub const DISCORD_DEFAULT_AVATAR_URL: &str = "https://cdn.discordapp.com/embed/avatars";
 pub const GENERIC_DAILY_LIMIT: u64 = 10;
+pub const SITE_URL: &str = "https://ask-lp.com";
 
 #[derive(Clone)]
 pub struct AppState {
+    config: Arc<config::Config>,
     db: db::Model,
+    /// Kept for the replica syncs, queries go through `db`
+    database: db::Database,
     oauth: BasicClient,
     http: reqwest::Client,
+    notifier: notify::Dispatcher,
+    /// REST client of the Discord bot, used to message users outside of gateway events. `None`
+    /// without a bot token.
+    discord: Option<Arc<serenity::http::Http>>,
+    /// Gateway shards of the bot, set once its client is built when this process runs it
+    shards: Arc<OnceLock<Arc<ShardManager>>>,
+    /// Signs the cookies that carry flash messages across redirects
+    cookie_key: Key,
+    jobs: jobs::Jobs,
+}
+
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Self {
+        state.cookie_key.clone()
+    }
 }
 
 #[tokio::main]
@@ -47,60 +73,112 @@ async fn main() {
     dotenv::dotenv().ok();
     tracing_subscriber::fmt::init();
 
-    #[cfg(debug_assertions)]
-    let port = match std::env::var("PORT") {
-        Ok(port) => port,
-        Err(_) => "3000".to_string(),
+    let mode = match std::env::args().nth(1) {
+        Some(arg) => match config::Mode::parse(&arg) {
+            Some(mode) => Some(mode),
+            None => {
+                eprintln!("Usage: asklp [serve|bot|all]");
+                std::process::exit(2);
+            }
+        },
+        None => None,
     };
-    #[cfg(not(debug_assertions))]
-    let port = "8080";
-    let port = port.parse::<u16>().unwrap_or(3000);
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-
-    info!("Listening on {}", addr);
+    let config = match config::Config::load(mode) {
+        Ok(config) => Arc::new(config),
+        Err(error::Error::Config(message)) => {
+            error!("{}", message);
+            std::process::exit(1);
+        }
+        Err(e) => panic!("Failed to load configuration: {:?}", e),
+    };
+    info!(
+        "Running {:?} with features {:?}",
+        config.mode, config.features
+    );
 
     // Set up database
-    let url = std::env::var("LIBSQL_URL").unwrap();
-    let token = std::env::var("LIBSQL_TOKEN").unwrap();
-    let db = Builder::new_remote_replica("local.db", url, token)
-        .sync_interval(Duration::from_secs(60))
-        .build()
+    let database = db::Database::open(&config.database)
         .await
-        .unwrap();
+        .expect("Failed to open database");
+    let model = match database.model().await {
+        Ok(model) => model,
+        Err(e) => panic!("Failed to migrate database: {}", e),
+    };
+
+    let http = reqwest::Client::new();
+    let discord = config
+        .discord
+        .bot_token
+        .as_ref()
+        .map(|token| Arc::new(serenity::http::Http::new(token)));
+    let notifier = notify::Dispatcher::from_config(&http, discord.as_ref(), &config)
+        .expect("Invalid notifier configuration");
+    info!("Notifiers enabled: {:?}", notifier.names());
 
-    db.sync().await.unwrap();
+    let state = AppState {
+        config: config.clone(),
+        db: model,
+        database,
+        oauth: auth::oauth_client(&config.discord).expect("Invalid Discord OAuth configuration"),
+        http,
+        notifier,
+        discord,
+        shards: Default::default(),
+        cookie_key: Key::derive_from(&config.encryption_key),
+        jobs: jobs::Jobs::default(),
+    };
 
-    let conn = db.connect().expect("Failed to connect to database");
+    let shutdown = shutdown::Shutdown::listen();
+    jobs::start(state.clone(), shutdown.clone());
 
     // Set up Discord bot
-    let token = std::env::var("DISCORD_BOT_TOKEN").expect("Expected a token in the environment");
-    let oai = oai::Client::new(&std::env::var("OPENAI_API_KEY").unwrap());
-    let intents = GatewayIntents::GUILD_MESSAGES
-        | GatewayIntents::DIRECT_MESSAGES
-        | GatewayIntents::MESSAGE_CONTENT;
-
-    let activity = ActivityData::watching("nbols");
-    let mut client = Client::builder(&token, intents)
-        .status(OnlineStatus::DoNotDisturb)
-        .activity(activity)
-        .event_handler(Handler::new(oai))
-        .await
-        .expect("Err creating client");
-
-    tokio::spawn(async move {
-        // Start listening for events by starting a single shard
-        match client.start().await {
-            Ok(_) => info!("Discord bot listening..."),
-            Err(why) => error!("Client error: {why:?}"),
+    let bot = match &config.discord.bot_token {
+        Some(token) if config.runs_bot() => Some(
+            bot::client(token, state.clone())
+                .await
+                .expect("Err creating client"),
+        ),
+        _ => {
+            info!("Not running the Discord bot");
+            None
         }
+    };
+
+    let mut bot = bot.map(|client| {
+        state.shards.set(client.shard_manager.clone()).ok();
+        tokio::spawn(bot::run(client, shutdown.clone()))
     });
+    if config.runs_server() {
+        serve(state.clone(), shutdown.clone()).await;
+    } else if let Some(bot) = &mut bot {
+        // Nothing else runs in this process, so it ends with the bot
+        tokio::select! {
+            _ = bot => {}
+            _ = shutdown.requested() => {}
+        }
+    }
 
-    let state = AppState {
-        db: db::Model::new(conn),
-        oauth: auth::oauth_client().unwrap(),
-        http: reqwest::Client::new(),
-    };
+    if let Some(bot) = bot.filter(|bot| !bot.is_finished()) {
+        if tokio::time::timeout(shutdown::BOT_TIMEOUT, bot)
+            .await
+            .is_err()
+        {
+            warn!("Timed out waiting for the Discord bot to disconnect");
+        }
+    }
+
+    let failed = state.notifier.failed_count();
+    if failed > 0 {
+        warn!("Dropping {} notifications waiting for a retry", failed);
+    }
+    match state.database.sync().await {
+        Ok(()) => info!("Synced the database one last time"),
+        Err(e) => error!("Failed to sync the database: {:?}", e),
+    }
+}
 
+/// Serves the web app and the API until shutdown, then drains in-flight requests
+async fn serve(state: AppState, shutdown: shutdown::Shutdown) {
     let app_router = Router::new()
         .route("/", routing::get(handlers::app::app))
         .route("/question/:id/answer", routing::get(handlers::app::answer))
@@ -108,6 +186,60 @@ async fn main() {
             "/question/:id/answer/submit",
             routing::post(handlers::app::submit_answer),
         )
+        .route("/askee", routing::post(handlers::app::set_askee))
+        .route("/settings", routing::get(handlers::app::settings))
+        .route(
+            "/settings/notifications",
+            routing::post(handlers::app::submit_notifications),
+        )
+        .route(
+            "/settings/timezone",
+            routing::post(handlers::app::submit_timezone),
+        )
+        .route(
+            "/tokens",
+            routing::get(handlers::tokens::tokens).post(handlers::tokens::create_token),
+        )
+        .route("/admin/users", routing::get(handlers::admin::users))
+        .route("/user/:id", routing::get(handlers::admin::user))
+        .route("/admin/limits", routing::get(handlers::admin::limits))
+        .route("/admin/jobs", routing::get(handlers::admin::jobs))
+        .route(
+            "/admin/limits/roles",
+            routing::post(handlers::admin::set_role_limits),
+        )
+        .route(
+            "/admin/limits/boosts",
+            routing::post(handlers::admin::create_boost),
+        )
+        .route(
+            "/admin/limits/boosts/:id/end",
+            routing::post(handlers::admin::end_boost),
+        )
+        .route("/user/:id/limit", routing::post(handlers::admin::set_limit))
+        .route(
+            "/user/:id/bonus",
+            routing::post(handlers::admin::grant_bonus),
+        )
+        .route("/user/:id/ban", routing::post(handlers::admin::set_banned))
+        .route(
+            "/user/:id/sessions/delete",
+            routing::post(handlers::admin::delete_sessions),
+        )
+        .route("/user/:id/askee", routing::post(handlers::admin::set_askee))
+        .route("/sessions", routing::get(handlers::sessions::sessions))
+        .route(
+            "/sessions/:id/revoke",
+            routing::post(handlers::sessions::revoke_session),
+        )
+        .route(
+            "/sessions/revoke-others",
+            routing::post(handlers::sessions::revoke_other_sessions),
+        )
+        .route(
+            "/tokens/:id/revoke",
+            routing::post(handlers::tokens::revoke_token),
+        )
         .route("/question/new", routing::get(handlers::app::new_question))
         .route(
             "/question/submit",
@@ -115,6 +247,22 @@ async fn main() {
         )
         .with_state(state.clone());
 
+    let api_router = Router::new()
+        .route("/me", routing::get(handlers::api::me))
+        .route("/askees", routing::get(handlers::api::askees))
+        .route("/admin/users", routing::get(handlers::api::admin_users))
+        .route(
+            "/questions",
+            routing::get(handlers::api::questions).post(handlers::api::create_question),
+        )
+        .route("/questions/:id", routing::get(handlers::api::question))
+        .route(
+            "/questions/:id/answer",
+            routing::get(handlers::api::answer).post(handlers::api::create_answer),
+        )
+        .fallback(handlers::api::not_found)
+        .with_state(state.clone());
+
     let static_router = Router::new()
         .nest_service("/", ServeDir::new("static"))
         .layer(CompressionLayer::new());
@@ -123,11 +271,17 @@ async fn main() {
 
     let limiter = RateLimiterLayer::new(global_quota);
 
+    // Probes poll these often and carry no session, they stay out of the auth and rate limit
+    // layers
+    let health_router = Router::new()
+        .route("/healthz", routing::get(handlers::health::healthz))
+        .route("/readyz", routing::get(handlers::health::readyz))
+        .with_state(state.clone());
+
     let app = Router::new()
         .route("/ping", routing::get(ping))
         .route("/", routing::get(index))
         .route("/logout", routing::get(handlers::logout))
-        .route("/users", routing::get(get_users))
         .route(
             "/discord/callback",
             routing::get(handlers::discord::discord_cb),
@@ -136,21 +290,43 @@ async fn main() {
             "/auth/discord",
             routing::get(handlers::discord::discord_auth),
         )
-        // .route("/questions", routing::get(handlers::questions::questions))
+        .route(
+            "/questions",
+            routing::get(handlers::questions::get_public_questions),
+        )
         .nest("/app", app_router)
+        .nest("/api/v1", api_router)
         .nest("/static", static_router)
         .fallback(not_found)
         .layer(middleware::from_fn_with_state(state.clone(), mw::auth))
         .layer(limiter)
-        .with_state(state.clone());
+        .with_state(state.clone())
+        .merge(health_router);
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], state.config.port));
+    info!("Listening on {}", addr);
 
     let listener = TcpListener::bind(addr).await.unwrap();
-    axum::serve(
+    let server = axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
-    .await
-    .unwrap();
+    .with_graceful_shutdown({
+        let shutdown = shutdown.clone();
+        async move { shutdown.requested().await }
+    });
+    let drain_timeout = async {
+        shutdown.requested().await;
+        tokio::time::sleep(shutdown::DRAIN_TIMEOUT).await;
+    };
+
+    tokio::select! {
+        res = server => res.unwrap(),
+        _ = drain_timeout => warn!(
+            "Gave up on requests still running after {}s",
+            shutdown::DRAIN_TIMEOUT.as_secs()
+        ),
+    }
 }
 
 async fn ping() -> &'static str {
@@ -163,14 +339,15 @@ async fn not_found() -> Result<NotFoundTemplate> {
     })
 }
 
-async fn get_users(state: State<AppState>) -> Result<impl IntoResponse> {
-    let users = state.db.get_users().await?;
-    Ok(Json(users))
+#[derive(Debug, serde::Deserialize)]
+struct IndexQuery {
+    /// The page the visitor was after before being asked to log in
+    next: Option<String>,
 }
 
-async fn index() -> Result<IndexTemplate> {
+async fn index(Query(query): Query<IndexQuery>) -> Result<IndexTemplate> {
     Ok(IndexTemplate {
-        login_url: "/auth/discord".to_string(),
+        login_url: handlers::discord::login_url(query.next.as_deref()),
     })
 }
 
@@ -187,8 +364,8 @@ pub struct AppTemplate {
     image_url: String,
     questions: Vec<QuestionUser>,
     q_count: usize,
-    remaining: u64,
-    user_limit: u64,
+    quota: handlers::app::Quota,
+    flash: Option<flash::Flash>,
 }
 
 #[derive(Template)]
@@ -196,6 +373,31 @@ pub struct AppTemplate {
 pub struct NewQuestionTemplate {
     image_url: String,
     user: db::User,
+    askees: Vec<db::User>,
+    selected_askee: Option<String>,
+    flash: Option<flash::Flash>,
+    /// What was submitted before the question was turned down
+    form: Option<handlers::app::NewQuestionForm>,
+}
+
+impl NewQuestionTemplate {
+    fn title(&self) -> &str {
+        self.form
+            .as_ref()
+            .map(|form| form.title.as_str())
+            .unwrap_or_default()
+    }
+
+    fn body(&self) -> &str {
+        self.form
+            .as_ref()
+            .and_then(|form| form.body.as_deref())
+            .unwrap_or_default()
+    }
+
+    fn public(&self) -> Option<bool> {
+        self.form.as_ref().map(|form| form.public)
+    }
 }
 
 #[derive(Template)]
@@ -0,0 +1,304 @@
+use libsql::{params, Connection};
+use std::fmt::Display;
+use tracing::info;
+
+/// A schema change embedded in the binary
+struct Migration {
+    version: u32,
+    name: &'static str,
+    sql: &'static str,
+    /// Query returning a reason to stop before applying `sql` to this database, for data the
+    /// migration cannot carry over on its own
+    refuse_if: Option<&'static str>,
+}
+
+/// 0002 gives existing questions to the first admin, they have no askee without one
+const ORPHAN_QUESTIONS: &str = "SELECT 'questions exist but no user is an admin to route them to, \
+    set is_admin = 1 on the user who answers them and restart'
+    WHERE EXISTS (SELECT 1 FROM Question)
+    AND NOT EXISTS (SELECT 1 FROM User WHERE is_admin = 1)";
+
+/// Applied in order on boot. Never edit a migration that has shipped, add a new one instead.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        sql: include_str!("../migrations/0001_init.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 2,
+        name: "askees",
+        sql: include_str!("../migrations/0002_askees.sql"),
+        refuse_if: Some(ORPHAN_QUESTIONS),
+    },
+    Migration {
+        version: 3,
+        name: "answer_notifications",
+        sql: include_str!("../migrations/0003_answer_notifications.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 4,
+        name: "unique_answers",
+        sql: include_str!("../migrations/0004_unique_answers.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 5,
+        name: "api_tokens",
+        sql: include_str!("../migrations/0005_api_tokens.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 6,
+        name: "user_bans",
+        sql: include_str!("../migrations/0006_user_bans.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 7,
+        name: "question_limits",
+        sql: include_str!("../migrations/0007_question_limits.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 8,
+        name: "user_timezones",
+        sql: include_str!("../migrations/0008_user_timezones.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 9,
+        name: "quota_policies",
+        sql: include_str!("../migrations/0009_quota_policies.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 10,
+        name: "default_avatars",
+        sql: include_str!("../migrations/0010_default_avatars.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 11,
+        name: "session_devices",
+        sql: include_str!("../migrations/0011_session_devices.sql"),
+        refuse_if: None,
+    },
+    Migration {
+        version: 12,
+        name: "job_runs",
+        sql: include_str!("../migrations/0012_job_runs.sql"),
+        refuse_if: None,
+    },
+];
+
+#[derive(Debug)]
+pub enum Error {
+    Db(libsql::Error),
+    /// The database was migrated by a newer build than this one
+    TooNew {
+        database: u32,
+        binary: u32,
+    },
+    /// The data needs fixing by hand before the migration can run
+    Refused {
+        version: u32,
+        reason: String,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Db(e) => write!(f, "database error: {}", e),
+            Error::TooNew { database, binary } => write!(
+                f,
+                "database schema is at version {} but this build only knows up to version {}",
+                database, binary
+            ),
+            Error::Refused { version, reason } => {
+                write!(f, "cannot apply migration {}: {}", version, reason)
+            }
+        }
+    }
+}
+
+impl From<libsql::Error> for Error {
+    fn from(e: libsql::Error) -> Self {
+        Error::Db(e)
+    }
+}
+
+/// Latest schema version known to this build
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+async fn current_version(conn: &Connection) -> Result<u32, Error> {
+    let mut rows = conn
+        .query("SELECT COALESCE(MAX(version), 0) FROM schema_version", ())
+        .await?;
+    let version = match rows.next().await? {
+        Some(row) => row.get::<u32>(0)?,
+        None => 0,
+    };
+    Ok(version)
+}
+
+/// Brings the schema up to date, returning the resulting version
+pub async fn run(conn: &Connection) -> Result<u32, Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at DATETIME NOT NULL
+        )",
+        (),
+    )
+    .await?;
+
+    let current = current_version(conn).await?;
+    let latest = latest_version();
+    if current > latest {
+        return Err(Error::TooNew {
+            database: current,
+            binary: latest,
+        });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        info!(
+            "Applying migration {} ({})",
+            migration.version, migration.name
+        );
+        if let Some(refuse_if) = migration.refuse_if {
+            let mut rows = conn.query(refuse_if, ()).await?;
+            if let Some(row) = rows.next().await? {
+                return Err(Error::Refused {
+                    version: migration.version,
+                    reason: row.get::<String>(0)?,
+                });
+            }
+        }
+        let tx = conn.transaction().await?;
+        tx.execute_batch(migration.sql).await?;
+        tx.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?,?,?)",
+            params!(
+                migration.version,
+                migration.name,
+                chrono::Utc::now().to_rfc3339()
+            ),
+        )
+        .await?;
+        tx.commit().await?;
+    }
+
+    Ok(latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsql::Builder;
+
+    async fn memory_conn() -> Connection {
+        let db = Builder::new_local(":memory:").build().await.unwrap();
+        db.connect().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_migrations_apply_once() {
+        let conn = memory_conn().await;
+
+        assert_eq!(run(&conn).await.unwrap(), latest_version());
+        assert_eq!(run(&conn).await.unwrap(), latest_version());
+        assert_eq!(current_version(&conn).await.unwrap(), latest_version());
+
+        let mut rows = conn
+            .query("SELECT COUNT(*) FROM schema_version", ())
+            .await
+            .unwrap();
+        let count = rows.next().await.unwrap().unwrap().get::<u32>(0).unwrap();
+        assert_eq!(count as usize, MIGRATIONS.len());
+    }
+
+    /// A database created from the schema file that predates migrations
+    async fn baseline_conn(admin: bool) -> Connection {
+        let conn = memory_conn().await;
+        conn.execute_batch(include_str!("../migrations/0001_init.sql"))
+            .await
+            .unwrap();
+        conn.execute(
+            "INSERT INTO User (id, username, discriminator, is_admin, joined_at, daily_questions)
+            VALUES ('lp', 'lp', '0', ?1, '2024-01-01T00:00:00+00:00', 0),
+                ('a', 'a', '0', 0, '2024-01-02T00:00:00+00:00', 2)",
+            params!(admin),
+        )
+        .await
+        .unwrap();
+        conn.execute_batch(
+            "INSERT INTO Question (id, title, body, public, created_at, user_id)
+            VALUES ('q', 'Hello', 'World', 1, '2024-01-03T00:00:00+00:00', 'a');
+            INSERT INTO Answer (id, body, created_at, user_id, question_id)
+            VALUES ('r', 'Hi', '2024-01-04T00:00:00+00:00', 'lp', 'q');",
+        )
+        .await
+        .unwrap();
+        conn
+    }
+
+    #[tokio::test]
+    async fn test_upgrades_baseline_database() {
+        let conn = baseline_conn(true).await;
+        assert_eq!(run(&conn).await.unwrap(), latest_version());
+
+        let mut rows = conn.query("SELECT * FROM Question", ()).await.unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        let question = libsql::de::from_row::<crate::db::Question>(&row).unwrap();
+        assert_eq!(question.askee_id, "lp");
+        assert_eq!(question.cost, 1);
+
+        let mut rows = conn
+            .query("SELECT * FROM User ORDER BY joined_at", ())
+            .await
+            .unwrap();
+        while let Some(row) = rows.next().await.unwrap() {
+            libsql::de::from_row::<crate::db::User>(&row).unwrap();
+        }
+
+        let mut rows = conn.query("SELECT * FROM Answer", ()).await.unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        libsql::de::from_row::<crate::db::Answer>(&row).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refuses_questions_without_an_admin() {
+        let conn = baseline_conn(false).await;
+        assert!(matches!(
+            run(&conn).await,
+            Err(Error::Refused { version: 2, .. })
+        ));
+        assert_eq!(current_version(&conn).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_newer_database() {
+        let conn = memory_conn().await;
+        run(&conn).await.unwrap();
+        conn.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?,?,?)",
+            params!(
+                latest_version() + 1,
+                "future",
+                chrono::Utc::now().to_rfc3339()
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(run(&conn).await, Err(Error::TooNew { .. })));
+    }
+}
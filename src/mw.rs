@@ -1,15 +1,22 @@
-use crate::{AppState, COOKIE_NAME};
+use crate::{
+    auth::Scopes, crypto::hash_token, db, error::Error, handlers::api::ApiError, AppState,
+    COOKIE_NAME,
+};
 use axum::{
     body::Body,
     extract::{ConnectInfo, Request, State},
     http::{header::LOCATION, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization, Cookie},
+    TypedHeader,
 };
-use axum_extra::{headers::Cookie, TypedHeader};
 use governor::{
     clock::DefaultClock, state::keyed::DashMapStateStore, Quota, RateLimiter as GovernorRateLimiter,
 };
+use oauth2::url::form_urlencoded;
 use std::{
     net::SocketAddr,
     sync::Arc,
@@ -25,25 +32,96 @@ pub fn redirect_to(location: &str) -> Response {
         .unwrap()
 }
 
+/// Id of the session the request was made with, absent for API token requests
+#[derive(Debug, Clone)]
+pub struct CurrentSession(pub String);
+
+/// Sends a signed out visitor to the landing page, remembering the page they wanted
+fn login_redirect(uri: &axum::http::Uri) -> Response {
+    let next = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/app");
+    if next == "/app" {
+        return redirect_to("/");
+    }
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("next", next)
+        .finish();
+    redirect_to(&format!("/?{}", query))
+}
+
+/// Resolves a personal API token to its owner and the scopes it was minted with
+async fn token_user(state: &AppState, token: &str) -> Option<(db::User, Scopes)> {
+    let token = state
+        .db
+        .get_api_token_by_hash(&hash_token(token))
+        .await
+        .ok()
+        .flatten()?;
+    let user = state.db.get_user(&token.user_id).await.ok().flatten()?;
+    if let Err(e) = state.db.touch_api_token(&token.id).await {
+        tracing::error!("Failed to update last use of token {}: {:?}", token.id, e);
+    }
+    Some((user, Scopes::parse(&token.scopes)))
+}
+
 /// Middleware for protected routes
 pub async fn auth(
     uri: axum::http::Uri,
-    TypedHeader(cookies): TypedHeader<Cookie>,
+    cookies: Option<TypedHeader<Cookie>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
     state: State<AppState>,
     mut req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
     let uri_path = uri.path();
-    let session_id = cookies.get(COOKIE_NAME);
 
+    // Nothing here depends on who is asking, skip the session lookup and its write
+    if uri_path.starts_with("/static/") || uri_path == "/ping" {
+        return Ok(next.run(req).await);
+    }
+
+    // API tokens are only accepted by the JSON API, the web app sticks to the session cookie
+    if uri_path.starts_with("/api") {
+        if let Some(TypedHeader(Authorization(bearer))) = bearer {
+            return match token_user(&state, bearer.token()).await {
+                Some((user, _)) if user.banned => Ok(ApiError::from(Error::Banned).into_response()),
+                Some((user, scopes)) => {
+                    req.extensions_mut().insert(user);
+                    req.extensions_mut().insert(scopes);
+                    Ok(next.run(req).await)
+                }
+                None => Ok(ApiError::from(Error::Unauthorized).into_response()),
+            };
+        }
+    }
+
+    let session_id = cookies
+        .as_ref()
+        .and_then(|TypedHeader(cookies)| cookies.get(COOKIE_NAME));
+
+    let mut current_session = None;
     let user = if let Some(session_id) = session_id {
         if let Some(session) = state
             .db
-            .get_active_session(&state.oauth, session_id)
+            .get_active_session(
+                &state.oauth,
+                &state.http,
+                &state.config.encryption_key,
+                session_id,
+            )
             .await
             .ok()
             .flatten()
         {
+            let now = chrono::Utc::now();
+            let seen_recently = session
+                .last_seen_at
+                .is_some_and(|at| now - at < db::SESSION_SEEN_INTERVAL);
+            if !seen_recently {
+                if let Err(e) = state.db.touch_session(&session.id, now).await {
+                    tracing::error!("Failed to update last use of session: {:?}", e);
+                }
+            }
+            current_session = Some(CurrentSession(session.id));
             state.db.get_user(&session.user_id).await.ok().flatten()
         } else {
             None
@@ -52,15 +130,31 @@ pub async fn auth(
         None
     };
 
-    if let Some(user) = user {
+    if let Some(user) = user.as_ref().filter(|user| user.banned) {
+        if uri_path.starts_with("/app") {
+            tracing::warn!("Banned user {} tried to use the app", user.id);
+            return Ok(Error::Banned.into_response());
+        } else if uri_path.starts_with("/api") {
+            return Ok(ApiError::from(Error::Banned).into_response());
+        }
+    }
+
+    // Banned users browse the public pages like anyone signed out
+    if let Some(user) = user.filter(|user| !user.banned) {
         if uri_path == "/" {
             Ok(redirect_to("/app"))
         } else {
             req.extensions_mut().insert(user);
+            req.extensions_mut().insert(Scopes::all());
+            if let Some(current_session) = current_session {
+                req.extensions_mut().insert(current_session);
+            }
             Ok(next.run(req).await)
         }
     } else if uri_path.starts_with("/app") {
-        Ok(redirect_to("/"))
+        Ok(login_redirect(&uri))
+    } else if uri_path.starts_with("/api") {
+        Ok(ApiError::from(Error::Unauthorized).into_response())
     } else {
         Ok(next.run(req).await)
     }
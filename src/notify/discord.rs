@@ -0,0 +1,40 @@
+use super::{Error, Notification, Notifier};
+use futures::future::BoxFuture;
+use serenity::{
+    all::{CreateMessage, UserId},
+    http::Http,
+};
+use std::{num::NonZeroU64, sync::Arc};
+
+/// Sends notifications as direct messages from the bot to the recipient
+pub struct DiscordDm {
+    http: Arc<Http>,
+}
+
+impl DiscordDm {
+    pub fn new(http: Arc<Http>) -> Self {
+        Self { http }
+    }
+}
+
+impl Notifier for DiscordDm {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let user_id = notification
+                .recipient_id
+                .parse::<NonZeroU64>()
+                .map_err(|e| Error::Discord(format!("invalid recipient id: {}", e)))?;
+            let content = format!("**{}**\n{}", notification.subject, notification.body);
+
+            UserId::from(user_id)
+                .direct_message(&self.http, CreateMessage::new().content(content))
+                .await
+                .map_err(|e| Error::Discord(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
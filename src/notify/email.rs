@@ -0,0 +1,62 @@
+use super::{Error, Notification, Notifier};
+use crate::{
+    config,
+    error::{Error as AppError, Result as AppResult},
+};
+use futures::future::BoxFuture;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+
+/// Sends notifications by email through an SMTP relay, to a single address so only for the
+/// askee it belongs to
+pub struct Email {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Mailbox,
+    askee_id: String,
+}
+
+impl Email {
+    pub fn new(config: &config::Smtp) -> AppResult<Self> {
+        let credentials = Credentials::new(config.username.clone(), config.password.clone());
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+            .map_err(|e| AppError::Config(format!("Invalid SMTP_HOST: {}", e)))?
+            .credentials(credentials)
+            .build();
+
+        Ok(Self {
+            transport,
+            from: config.from.clone(),
+            to: config.to.clone(),
+            askee_id: config.askee_id.clone(),
+        })
+    }
+}
+
+impl Notifier for Email {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            if notification.recipient_id != self.askee_id {
+                return Ok(());
+            }
+            let message = Message::builder()
+                .from(self.from.clone())
+                .to(self.to.clone())
+                .subject(&notification.subject)
+                .body(notification.body.clone())
+                .map_err(|e| Error::Email(e.to_string()))?;
+
+            self.transport
+                .send(message)
+                .await
+                .map_err(|e| Error::Email(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
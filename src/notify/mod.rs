@@ -0,0 +1,345 @@
+use crate::{
+    config::{self, Config},
+    error::{Error as AppError, Result as AppResult},
+};
+use futures::future::BoxFuture;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{error, info, warn};
+
+pub mod discord;
+pub mod email;
+pub mod twilio;
+pub mod webhook;
+
+/// How many times a notification is attempted before it is dropped
+const MAX_ATTEMPTS: u32 = 4;
+/// Delay before the first retry, doubled after every failed attempt
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// How many times the retry job tries a notification that ran out of attempts
+const MAX_REQUEUES: u32 = 10;
+/// Notifications waiting for the retry job, the oldest are dropped past this
+const MAX_FAILED: usize = 100;
+
+/// A message sent to an askee when something happens in the app
+#[derive(Debug, Clone)]
+pub struct Notification {
+    /// Discord id of the user the notification is about
+    pub recipient_id: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    Status(reqwest::StatusCode, String),
+    Email(String),
+    Discord(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "http error: {}", e),
+            Error::Status(status, body) => write!(f, "unexpected status {}: {}", status, body),
+            Error::Email(e) => write!(f, "email error: {}", e),
+            Error::Discord(e) => write!(f, "discord error: {}", e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+/// A backend able to deliver notifications
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+/// A notification one backend could not deliver, kept for the retry job
+#[derive(Debug, Clone)]
+struct Failed {
+    /// Index of the backend in [`Dispatcher::notifiers`]
+    notifier: usize,
+    notification: Notification,
+    requeues: u32,
+}
+
+/// Outcome of [`Dispatcher::retry_failed`]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Retried {
+    pub sent: usize,
+    pub requeued: usize,
+    pub dropped: usize,
+}
+
+/// Fans notifications out to every configured backend
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    notifiers: Arc<Vec<Box<dyn Notifier>>>,
+    failed: Arc<Mutex<Vec<Failed>>>,
+}
+
+impl Dispatcher {
+    pub fn new(notifiers: Vec<Box<dyn Notifier>>) -> Self {
+        Self {
+            notifiers: Arc::new(notifiers),
+            failed: Default::default(),
+        }
+    }
+
+    /// Builds the backends listed in `NOTIFIERS`. `discord` is the bot's REST client, shared with
+    /// the rest of the app.
+    pub fn from_config(
+        http: &reqwest::Client,
+        discord: Option<&Arc<serenity::http::Http>>,
+        config: &Config,
+    ) -> AppResult<Self> {
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+        for notifier in &config.notifiers {
+            let notifier: Box<dyn Notifier> = match notifier {
+                config::Notifier::Twilio(twilio) => {
+                    Box::new(twilio::Twilio::new(http.clone(), twilio))
+                }
+                config::Notifier::Webhook { url } => {
+                    Box::new(webhook::Webhook::new(http.clone(), url.clone()))
+                }
+                config::Notifier::Email(smtp) => Box::new(email::Email::new(smtp)?),
+                config::Notifier::Discord => match discord {
+                    Some(discord) => Box::new(discord::DiscordDm::new(discord.clone())),
+                    None => {
+                        return Err(AppError::Config(
+                            "The discord notifier needs the bot's client".to_string(),
+                        ))
+                    }
+                },
+            };
+            notifiers.push(notifier);
+        }
+        Ok(Self::new(notifiers))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.notifiers.iter().map(|n| n.name()).collect()
+    }
+
+    /// Sends the notification through every backend in the background, retrying failures.
+    /// Notifications that still fail are left for [`Dispatcher::retry_failed`].
+    pub fn dispatch(&self, notification: Notification) {
+        for index in 0..self.notifiers.len() {
+            self.send(index, notification.clone());
+        }
+    }
+
+    /// Like [`Dispatcher::dispatch`] but only through the backend called `name`. False when that
+    /// backend is not configured.
+    pub fn dispatch_to(&self, name: &str, notification: Notification) -> bool {
+        match self.notifiers.iter().position(|n| n.name() == name) {
+            Some(index) => {
+                self.send(index, notification);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn send(&self, index: usize, notification: Notification) {
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            let notifier = dispatcher.notifiers[index].as_ref();
+            if !send_with_retry(notifier, &notification, RETRY_DELAY).await {
+                dispatcher.push_failed(Failed {
+                    notifier: index,
+                    notification,
+                    requeues: 0,
+                });
+            }
+        });
+    }
+
+    fn push_failed(&self, failed: Failed) {
+        let mut queue = self.failed.lock().unwrap();
+        if queue.len() >= MAX_FAILED {
+            let dropped = queue.remove(0);
+            error!(
+                "Retry queue is full, dropping notification {:?}",
+                dropped.notification.subject
+            );
+        }
+        queue.push(failed);
+    }
+
+    /// Notifications waiting to be retried
+    pub fn failed_count(&self) -> usize {
+        self.failed.lock().unwrap().len()
+    }
+
+    /// Tries every notification that ran out of attempts once more
+    pub async fn retry_failed(&self) -> Retried {
+        let queue = std::mem::take(&mut *self.failed.lock().unwrap());
+        let mut retried = Retried::default();
+        for mut failed in queue {
+            let notifier = self.notifiers[failed.notifier].as_ref();
+            match notifier.notify(&failed.notification).await {
+                Ok(()) => retried.sent += 1,
+                Err(e) if failed.requeues + 1 < MAX_REQUEUES => {
+                    warn!("Notifier {} failed again: {}", notifier.name(), e);
+                    failed.requeues += 1;
+                    retried.requeued += 1;
+                    self.push_failed(failed);
+                }
+                Err(e) => {
+                    error!(
+                        "Notifier {} dropped {:?}: {}",
+                        notifier.name(),
+                        failed.notification.subject,
+                        e
+                    );
+                    retried.dropped += 1;
+                }
+            }
+        }
+        retried
+    }
+}
+
+/// Returns whether the notification was delivered
+async fn send_with_retry(
+    notifier: &dyn Notifier,
+    notification: &Notification,
+    delay: Duration,
+) -> bool {
+    let mut delay = delay;
+    for attempt in 1..=MAX_ATTEMPTS {
+        match notifier.notify(notification).await {
+            Ok(()) => {
+                info!("Sent notification through {}", notifier.name());
+                return true;
+            }
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                warn!(
+                    "Notifier {} failed (attempt {}/{}): {}",
+                    notifier.name(),
+                    attempt,
+                    MAX_ATTEMPTS,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => {
+                error!(
+                    "Notifier {} gave up after {} attempts: {}",
+                    notifier.name(),
+                    MAX_ATTEMPTS,
+                    e
+                );
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    struct Flaky {
+        calls: Arc<AtomicU32>,
+        failures: u32,
+    }
+
+    impl Notifier for Flaky {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn notify<'a>(&'a self, _: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+            Box::pin(async move {
+                let call = self.calls.fetch_add(1, Ordering::SeqCst);
+                if call < self.failures {
+                    Err(Error::Email("unavailable".to_string()))
+                } else {
+                    Ok(())
+                }
+            })
+        }
+    }
+
+    fn notification() -> Notification {
+        Notification {
+            recipient_id: "1".to_string(),
+            subject: "New question".to_string(),
+            body: "Hello, world!".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let notifier = Flaky {
+            calls: calls.clone(),
+            failures: 2,
+        };
+
+        assert!(send_with_retry(&notifier, &notification(), Duration::ZERO).await);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let notifier = Flaky {
+            calls: calls.clone(),
+            failures: u32::MAX,
+        };
+
+        assert!(!send_with_retry(&notifier, &notification(), Duration::ZERO).await);
+        assert_eq!(calls.load(Ordering::SeqCst), MAX_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn test_retry_failed() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let dispatcher = Dispatcher::new(vec![Box::new(Flaky {
+            calls: calls.clone(),
+            failures: 1,
+        })]);
+        for _ in 0..2 {
+            dispatcher.push_failed(Failed {
+                notifier: 0,
+                notification: notification(),
+                requeues: 0,
+            });
+        }
+        dispatcher.push_failed(Failed {
+            notifier: 0,
+            notification: notification(),
+            requeues: MAX_REQUEUES - 1,
+        });
+
+        // The first one fails and goes back in the queue, the others are sent
+        let retried = dispatcher.retry_failed().await;
+        assert_eq!(
+            retried,
+            Retried {
+                sent: 2,
+                requeued: 1,
+                dropped: 0
+            }
+        );
+        assert_eq!(dispatcher.failed_count(), 1);
+
+        let retried = dispatcher.retry_failed().await;
+        assert_eq!(retried.sent, 1);
+        assert_eq!(dispatcher.failed_count(), 0);
+    }
+}
@@ -0,0 +1,89 @@
+use super::{Error, Notification, Notifier};
+use crate::config;
+use futures::future::BoxFuture;
+
+/// Sends notifications as SMS to a single phone number, so only for the askee it belongs to
+pub struct Twilio {
+    http: reqwest::Client,
+    account_sid: String,
+    auth_token: String,
+    message_service_sid: String,
+    to: String,
+    askee_id: String,
+}
+
+impl Twilio {
+    pub fn new(http: reqwest::Client, config: &config::Twilio) -> Self {
+        Self {
+            http,
+            account_sid: config.account_sid.clone(),
+            auth_token: config.auth_token.clone(),
+            message_service_sid: config.message_service_sid.clone(),
+            to: config.to.clone(),
+            askee_id: config.askee_id.clone(),
+        }
+    }
+
+    pub async fn send_sms(&self, body: &str) -> Result<(), Error> {
+        let res = self
+            .http
+            .post(format!(
+                "https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json",
+                self.account_sid
+            ))
+            .basic_auth(&self.account_sid, Some(&self.auth_token))
+            .form(&[
+                ("MessagingServiceSid", self.message_service_sid.as_str()),
+                ("To", self.to.as_str()),
+                ("Body", body),
+            ])
+            .send()
+            .await?;
+
+        let status = res.status();
+        if !status.is_success() {
+            return Err(Error::Status(status, res.text().await?));
+        }
+        Ok(())
+    }
+}
+
+impl Notifier for Twilio {
+    fn name(&self) -> &'static str {
+        "twilio"
+    }
+
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            if notification.recipient_id != self.askee_id {
+                return Ok(());
+            }
+            self.send_sms(&notification.body).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_send_sms() {
+        dotenv::dotenv().ok();
+        let config = config::Config::load(None).unwrap();
+        let twilio = config
+            .notifiers
+            .iter()
+            .find_map(|notifier| match notifier {
+                config::Notifier::Twilio(twilio) => {
+                    Some(Twilio::new(reqwest::Client::new(), twilio))
+                }
+                _ => None,
+            })
+            .expect("Twilio is not configured");
+
+        let body = "Hello, world!";
+        let res = twilio.send_sms(body).await;
+        assert!(res.is_ok());
+    }
+}
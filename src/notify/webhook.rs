@@ -0,0 +1,38 @@
+use super::{Error, Notification, Notifier};
+use futures::future::BoxFuture;
+use serde_json::json;
+
+/// POSTs notifications as JSON to `WEBHOOK_URL`
+pub struct Webhook {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl Webhook {
+    pub fn new(http: reqwest::Client, url: String) -> Self {
+        Self { http, url }
+    }
+}
+
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn notify<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let body = json!({
+                "recipient_id": notification.recipient_id,
+                "subject": notification.subject,
+                "body": notification.body,
+            });
+            let res = self.http.post(&self.url).json(&body).send().await?;
+
+            let status = res.status();
+            if !status.is_success() {
+                return Err(Error::Status(status, res.text().await?));
+            }
+            Ok(())
+        })
+    }
+}
@@ -1,4 +1,3 @@
-use crate::ADMIN_ID;
 use reqwest::header::{HeaderMap, AUTHORIZATION};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -48,14 +47,15 @@ impl Client {
             base_url: "https://api.openai.com/v1".to_string(),
         }
     }
-    /// Create a new chat completion request
+    /// Create a new chat completion request checking if `content` asks `askee_id` a question
     pub async fn create_chat_completion(
         &self,
         model: Model,
+        askee_id: &str,
         content: String,
     ) -> Result<ChatCompletionResponse, Error> {
         let app_ctx =
-            format!("your role is to analyze the context of a discord message to figure out if the user is asking a question to the user {}. Only answer with true or false. Messages might be in French or English, or a mix.", ADMIN_ID);
+            format!("your role is to analyze the context of a discord message to figure out if the user is asking a question to the user {}. Only answer with true or false. Messages might be in French or English, or a mix.", askee_id);
         let url = format!("{}/chat/completions", self.base_url);
         let messages = vec![
             (GptRole::System, app_ctx).into_gpt_message(),
@@ -163,13 +163,15 @@ impl Display for Model {
 mod tests {
     use super::*;
 
+    const ASKEE_ID: &str = "173963703606181888";
+
     #[tokio::test]
     async fn test_validate_no_question() {
         let key = std::env::var("OPENAI_API_KEY").unwrap();
         let message = std::fs::read_to_string("tests/sample-mention-no-q.json").unwrap();
         let client = Client::new(&key);
         let res = client
-            .create_chat_completion(Model::Gpt4oMini, message)
+            .create_chat_completion(Model::Gpt4oMini, ASKEE_ID, message)
             .await
             .unwrap();
 
@@ -185,7 +187,7 @@ mod tests {
         let message = std::fs::read_to_string("tests/sample-mention-q.json").unwrap();
         let client = Client::new(&key);
         let res = client
-            .create_chat_completion(Model::Gpt4oMini, message)
+            .create_chat_completion(Model::Gpt4oMini, ASKEE_ID, message)
             .await
             .unwrap();
 
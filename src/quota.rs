@@ -0,0 +1,346 @@
+use crate::{
+    db::{self, QuestionAllowance},
+    time::{next_reset, quota_day, start_of_day},
+};
+use chrono::{DateTime, Datelike, Duration, Utc};
+use chrono_tz::Tz;
+
+/// How a user's limit is spread over time. Picked per deployment with `QUOTA_POLICY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaPolicy {
+    /// The limit resets at midnight in the user's timezone
+    Daily,
+    /// The limit applies to the last 24 hours
+    Rolling,
+    /// The limit resets on Monday at midnight in the user's timezone
+    Weekly,
+    /// The whole limit can be spent at once, then refills evenly over a day
+    Burst,
+}
+
+impl QuotaPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "daily" => Some(QuotaPolicy::Daily),
+            "rolling" => Some(QuotaPolicy::Rolling),
+            "weekly" => Some(QuotaPolicy::Weekly),
+            "burst" => Some(QuotaPolicy::Burst),
+            _ => None,
+        }
+    }
+
+    /// Ends the "N questions remaining out of M" sentence
+    pub fn period(&self, limit: u64) -> String {
+        match self {
+            QuotaPolicy::Daily => "today".to_string(),
+            QuotaPolicy::Rolling => "in the last 24 hours".to_string(),
+            QuotaPolicy::Weekly => "this week".to_string(),
+            QuotaPolicy::Burst => {
+                let minutes = refill_interval(limit).num_minutes();
+                if minutes >= 60 && minutes % 60 == 0 {
+                    format!("with one more every {}h", minutes / 60)
+                } else if minutes >= 60 {
+                    format!("with one more every {}h {}m", minutes / 60, minutes % 60)
+                } else {
+                    format!("with one more every {}m", minutes.max(1))
+                }
+            }
+        }
+    }
+}
+
+/// The burst policy refills the whole limit over a day
+fn refill_interval(limit: u64) -> Duration {
+    Duration::seconds(86_400 / limit.max(1) as i64)
+}
+
+/// `interval` repeated `n` times, `None` when that does not fit in a `Duration`
+fn times(interval: Duration, n: u64) -> Option<Duration> {
+    interval.checked_mul(i32::try_from(n).ok()?)
+}
+
+/// Cost of a question against the quota. Read from `QUESTION_COST_PUBLIC`,
+/// `QUESTION_COST_PRIVATE`, and `QUESTION_COST_LONG` for bodies over `QUESTION_LONG_BODY`
+/// characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuestionCost {
+    pub public: u64,
+    pub private: u64,
+    /// Extra cost for bodies longer than `long_body` characters
+    pub long: u64,
+    pub long_body: Option<usize>,
+}
+
+impl Default for QuestionCost {
+    fn default() -> Self {
+        Self {
+            public: 1,
+            private: 1,
+            long: 0,
+            long_body: None,
+        }
+    }
+}
+
+impl QuestionCost {
+    pub fn cost(&self, public: bool, body: &str) -> u64 {
+        let base = if public { self.public } else { self.private };
+        let long = match self.long_body {
+            Some(chars) if body.chars().count() > chars => self.long,
+            _ => 0,
+        };
+        base + long
+    }
+
+    /// Explains costs that are not one question each, if any
+    pub fn describe(&self) -> Option<String> {
+        let mut notes = Vec::new();
+        if self.public != 1 || self.private != 1 {
+            notes.push(format!(
+                "public questions count as {} and private ones as {}",
+                self.public, self.private
+            ));
+        }
+        if let Some(chars) = self.long_body.filter(|_| self.long > 0) {
+            notes.push(format!(
+                "bodies over {} characters count {} more",
+                chars, self.long
+            ));
+        }
+        if notes.is_empty() {
+            None
+        } else {
+            Some(notes.join(", "))
+        }
+    }
+}
+
+/// How much of the limit is spent at `now`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub used: u64,
+    /// When some of what is used frees up again
+    pub frees_at: DateTime<Tz>,
+}
+
+/// Where the burst policy stands: units in use and when the next one frees up.
+/// `tat` is the theoretical arrival time of the generic cell rate algorithm, every unit spent
+/// pushes it `interval` further into the future.
+fn burst_usage(
+    tat: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    interval: Duration,
+) -> (u64, DateTime<Utc>) {
+    let debt = match tat {
+        Some(tat) if tat > now => tat - now,
+        _ => return (0, now),
+    };
+    let interval_ms = interval.num_milliseconds().max(1);
+    let debt_ms = debt.num_milliseconds();
+    let used = ((debt_ms + interval_ms - 1) / interval_ms) as u64;
+    let frees_at = now + Duration::milliseconds(debt_ms - (used as i64 - 1) * interval_ms);
+    (used, frees_at)
+}
+
+/// Start of the week containing `now`, weeks start on Monday
+fn start_of_week(now: DateTime<Utc>, tz: &Tz) -> DateTime<Tz> {
+    let today = quota_day(now, tz);
+    let monday = today - Duration::days(today.weekday().num_days_from_monday().into());
+    start_of_day(monday, tz)
+}
+
+impl QuotaPolicy {
+    pub async fn usage(
+        &self,
+        db: &db::Model,
+        user: &db::User,
+        limit: u64,
+        now: DateTime<Utc>,
+        tz: &Tz,
+    ) -> std::result::Result<Usage, db::Error> {
+        let usage = match self {
+            QuotaPolicy::Daily => Usage {
+                used: user.questions_used_on(quota_day(now, tz)),
+                frees_at: next_reset(now, tz),
+            },
+            QuotaPolicy::Rolling => {
+                let since = now - Duration::hours(24);
+                let costs = db.get_question_costs_since(&user.id, since).await?;
+                let oldest = costs.first().map(|(created_at, _)| *created_at);
+                Usage {
+                    used: costs.iter().map(|(_, cost)| cost).sum(),
+                    frees_at: (oldest.unwrap_or(since) + Duration::hours(24)).with_timezone(tz),
+                }
+            }
+            QuotaPolicy::Weekly => {
+                let start = start_of_week(now, tz);
+                let next = start_of_day(start.date_naive() + Duration::days(7), tz);
+                let costs = db
+                    .get_question_costs_since(&user.id, start.with_timezone(&Utc))
+                    .await?;
+                Usage {
+                    used: costs.iter().map(|(_, cost)| cost).sum(),
+                    frees_at: next,
+                }
+            }
+            QuotaPolicy::Burst => {
+                let (used, frees_at) = burst_usage(user.quota_tat, now, refill_interval(limit));
+                Usage {
+                    used,
+                    frees_at: frees_at.with_timezone(tz),
+                }
+            }
+        };
+        Ok(usage)
+    }
+
+    /// Spends `cost` out of the limit, or out of the user's bonus questions once the limit is
+    /// reached. `None` when neither has enough left.
+    pub async fn spend(
+        &self,
+        db: &db::Model,
+        user: &db::User,
+        limit: u64,
+        cost: u64,
+        now: DateTime<Utc>,
+        tz: &Tz,
+    ) -> std::result::Result<Option<QuestionAllowance>, db::Error> {
+        let spent = match self {
+            QuotaPolicy::Daily => {
+                db.use_daily_questions(&user.id, quota_day(now, tz), limit, cost)
+                    .await?
+            }
+            // The question row is the record of the spend, two questions sent at the very same
+            // moment can both fit
+            QuotaPolicy::Rolling | QuotaPolicy::Weekly => {
+                let usage = self.usage(db, user, limit, now, tz).await?;
+                usage.used.saturating_add(cost) <= limit
+            }
+            QuotaPolicy::Burst => {
+                let interval = refill_interval(limit);
+                let start = user.quota_tat.filter(|tat| *tat > now).unwrap_or(now);
+                let tat = times(interval, cost).and_then(|spend| start.checked_add_signed(spend));
+                match (tat, times(interval, limit)) {
+                    (Some(tat), Some(burst)) if tat - now <= burst => {
+                        db.use_burst(&user.id, user.quota_tat, tat).await?
+                    }
+                    _ => false,
+                }
+            }
+        };
+        if spent {
+            return Ok(Some(QuestionAllowance::Limit));
+        }
+
+        Ok(db
+            .use_bonus_questions(&user.id, cost)
+            .await?
+            .then_some(QuestionAllowance::Bonus))
+    }
+
+    /// Gives back what [`QuotaPolicy::spend`] took with the same arguments, for when the
+    /// question could not be saved afterwards. A burst spend stays taken when another question
+    /// was spent since.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn refund(
+        &self,
+        db: &db::Model,
+        user: &db::User,
+        limit: u64,
+        allowance: QuestionAllowance,
+        cost: u64,
+        now: DateTime<Utc>,
+        tz: &Tz,
+    ) -> std::result::Result<(), db::Error> {
+        match (allowance, self) {
+            (QuestionAllowance::Bonus, _) => db.add_bonus_questions(&user.id, cost).await,
+            (QuestionAllowance::Limit, QuotaPolicy::Daily) => {
+                db.refund_daily_questions(&user.id, quota_day(now, tz), cost)
+                    .await
+            }
+            // Nothing is recorded until the question row is saved
+            (QuestionAllowance::Limit, QuotaPolicy::Rolling | QuotaPolicy::Weekly) => Ok(()),
+            (QuestionAllowance::Limit, QuotaPolicy::Burst) => {
+                let start = user.quota_tat.filter(|tat| *tat > now).unwrap_or(now);
+                let tat = times(refill_interval(limit), cost)
+                    .and_then(|spend| start.checked_add_signed(spend));
+                if let Some(tat) = tat {
+                    db.use_burst(&user.id, Some(tat), start).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use chrono_tz::America;
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(QuotaPolicy::parse("weekly"), Some(QuotaPolicy::Weekly));
+        assert_eq!(QuotaPolicy::parse(" burst "), Some(QuotaPolicy::Burst));
+        assert_eq!(QuotaPolicy::parse("monthly"), None);
+        assert_eq!(QuotaPolicy::Burst.period(10), "with one more every 2h 24m");
+        assert_eq!(QuotaPolicy::Burst.period(4), "with one more every 6h");
+    }
+
+    #[test]
+    fn test_times_does_not_overflow() {
+        let interval = refill_interval(10);
+        assert_eq!(times(interval, 3), Some(Duration::minutes(432)));
+        assert_eq!(times(interval, u64::MAX), None);
+        assert_eq!(times(Duration::days(365_000), i32::MAX as u64), None);
+    }
+
+    #[test]
+    fn test_question_cost() {
+        let cost = QuestionCost::default();
+        assert_eq!(cost.cost(true, "body"), 1);
+        assert_eq!(cost.cost(false, &"x".repeat(1000)), 1);
+        assert!(cost.describe().is_none());
+
+        let cost = QuestionCost {
+            public: 1,
+            private: 2,
+            long: 3,
+            long_body: Some(10),
+        };
+        assert_eq!(cost.cost(true, "short"), 1);
+        assert_eq!(cost.cost(false, "short"), 2);
+        assert_eq!(cost.cost(false, "much longer body"), 5);
+        assert_eq!(cost.cost(true, "éééééééééé"), 1);
+        assert!(cost.describe().is_some());
+    }
+
+    #[test]
+    fn test_burst_usage() {
+        let now = Utc.with_ymd_and_hms(2024, 11, 15, 12, 0, 0).unwrap();
+        let hour = Duration::hours(1);
+
+        assert_eq!(burst_usage(None, now, hour), (0, now));
+        assert_eq!(burst_usage(Some(now - hour), now, hour), (0, now));
+        // Two and a half units are still refilling, the half frees up first
+        let (used, frees_at) = burst_usage(Some(now + hour * 5 / 2), now, hour);
+        assert_eq!(used, 3);
+        assert_eq!(frees_at, now + Duration::minutes(30));
+        let (used, frees_at) = burst_usage(Some(now + hour * 2), now, hour);
+        assert_eq!(used, 2);
+        assert_eq!(frees_at, now + hour);
+    }
+
+    #[test]
+    fn test_start_of_week() {
+        let tz = America::New_York;
+        // Sunday evening in New York is already Monday in UTC
+        let sunday = tz.with_ymd_and_hms(2024, 11, 17, 22, 0, 0).unwrap();
+        let start = start_of_week(sunday.with_timezone(&Utc), &tz);
+        assert_eq!(start, tz.with_ymd_and_hms(2024, 11, 11, 0, 0, 0).unwrap());
+
+        let monday = tz.with_ymd_and_hms(2024, 11, 18, 0, 0, 0).unwrap();
+        assert_eq!(start_of_week(monday.with_timezone(&Utc), &tz), monday);
+    }
+}
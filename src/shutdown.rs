@@ -0,0 +1,58 @@
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::info;
+
+/// How long in-flight requests get to finish once a shutdown starts. Shorter than the
+/// `kill_timeout` in fly.toml so the final replica sync still gets to run.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long the bot gets to close its gateway connection
+pub const BOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tells the server, the bot and the jobs when the process was asked to stop
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Listens for SIGTERM, which fly.io sends on deploys, and for Ctrl+C
+    pub fn listen() -> Self {
+        let (tx, requested) = watch::channel(false);
+        tokio::spawn(async move {
+            signal().await;
+            info!("Shutting down");
+            tx.send(true).ok();
+        });
+        Self { requested }
+    }
+
+    /// Resolves once a shutdown was requested
+    pub async fn requested(&self) {
+        let mut requested = self.requested.clone();
+        // Checks the current value first, so a shutdown requested earlier resolves right away
+        requested.wait_for(|requested| *requested).await.ok();
+    }
+}
+
+async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
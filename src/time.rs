@@ -1,4 +1,8 @@
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::{America, Tz};
+
+/// Daily question limits reset at midnight in this timezone unless `QUOTA_TIMEZONE` says otherwise
+pub const DEFAULT_QUOTA_TIMEZONE: Tz = America::New_York;
 
 pub trait TimeDisplay {
     fn time_ago(&self) -> String;
@@ -27,10 +31,47 @@ impl<Tz: TimeZone> TimeDisplay for DateTime<Tz> {
 pub fn time_ago(dt: &DateTime<Utc>) -> String {
     dt.time_ago()
 }
+
+/// The day a question asked at `now` counts towards
+pub fn quota_day(now: DateTime<Utc>, tz: &Tz) -> NaiveDate {
+    now.with_timezone(tz).date_naive()
+}
+
+/// First instant of `day` in `tz`. When a DST change repeats midnight the earlier one wins, and
+/// when it skips midnight the day starts at the first local time that exists.
+pub fn start_of_day(day: NaiveDate, tz: &Tz) -> DateTime<Tz> {
+    let midnight = day.and_time(NaiveTime::MIN);
+    (0..24 * 60)
+        .find_map(|minutes| {
+            match tz.from_local_datetime(&(midnight + Duration::minutes(minutes))) {
+                LocalResult::Single(dt) => Some(dt),
+                LocalResult::Ambiguous(earliest, _) => Some(earliest),
+                LocalResult::None => None,
+            }
+        })
+        .expect("a day always has a valid local time")
+}
+
+/// Half-open `[start, end)` quota day containing `now`, DST days can be 23 or 25 hours long
+pub fn quota_window(now: DateTime<Utc>, tz: &Tz) -> (DateTime<Tz>, DateTime<Tz>) {
+    let day = quota_day(now, tz);
+    let next = day.succ_opt().expect("date out of range");
+    (start_of_day(day, tz), start_of_day(next, tz))
+}
+
+/// When the quota of the day containing `now` resets
+pub fn next_reset(now: DateTime<Utc>, tz: &Tz) -> DateTime<Tz> {
+    quota_window(now, tz).1
+}
+
+/// Parses an IANA timezone name such as `Europe/Paris`
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    name.trim().parse().ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::Duration;
 
     #[test]
     fn test_time_ago() {
@@ -48,4 +89,91 @@ mod tests {
         assert_eq!(days_ago.time_ago(), "2d ago");
         assert_eq!(weeks_ago.time_ago(), "3w ago");
     }
+
+    fn local(tz: &Tz, y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> DateTime<Utc> {
+        tz.with_ymd_and_hms(y, m, d, h, min, s)
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_quota_day() {
+        let tz = America::New_York;
+        let eastern = |y, m, d, h, min, s| local(&tz, y, m, d, h, min, s);
+
+        assert_eq!(
+            quota_day(eastern(2024, 11, 14, 23, 59, 59), &tz),
+            day(2024, 11, 14)
+        );
+        assert_eq!(
+            quota_day(eastern(2024, 11, 15, 0, 0, 0), &tz),
+            day(2024, 11, 15)
+        );
+        // Already the next day in UTC
+        assert_eq!(
+            quota_day(eastern(2024, 11, 15, 23, 0, 0), &tz),
+            day(2024, 11, 15)
+        );
+        assert_eq!(
+            quota_day(eastern(2024, 11, 16, 0, 0, 0), &tz),
+            day(2024, 11, 16)
+        );
+
+        let paris = chrono_tz::Europe::Paris;
+        let now = eastern(2024, 11, 15, 20, 0, 0);
+        assert_eq!(quota_day(now, &paris), day(2024, 11, 16));
+    }
+
+    #[test]
+    fn test_quota_window_is_half_open() {
+        let tz = America::New_York;
+        let (start, end) = quota_window(local(&tz, 2024, 11, 15, 23, 59, 59), &tz);
+        assert_eq!(start.with_timezone(&Utc), local(&tz, 2024, 11, 15, 0, 0, 0));
+        assert_eq!(end.with_timezone(&Utc), local(&tz, 2024, 11, 16, 0, 0, 0));
+        assert_eq!(quota_window(end.with_timezone(&Utc), &tz).0, end);
+    }
+
+    #[test]
+    fn test_quota_window_across_dst() {
+        let tz = America::New_York;
+        // Spring forward, the day is 23 hours long
+        let (start, end) = quota_window(local(&tz, 2024, 3, 10, 12, 0, 0), &tz);
+        assert_eq!(end - start, Duration::hours(23));
+        // Fall back, the day is 25 hours long
+        let (start, end) = quota_window(local(&tz, 2024, 11, 3, 12, 0, 0), &tz);
+        assert_eq!(end - start, Duration::hours(25));
+    }
+
+    #[test]
+    fn test_start_of_day_when_midnight_is_skipped_or_repeated() {
+        // Chile moved its clocks from midnight straight to 1am
+        let santiago = America::Santiago;
+        let start = start_of_day(day(2022, 9, 11), &santiago);
+        assert_eq!(
+            start.naive_local(),
+            day(2022, 9, 11).and_hms_opt(1, 0, 0).unwrap()
+        );
+
+        // Cuba went from 1am back to midnight, so midnight happened twice
+        let havana = America::Havana;
+        let midnight = day(2023, 11, 5).and_time(NaiveTime::MIN);
+        let LocalResult::Ambiguous(earliest, _) = havana.from_local_datetime(&midnight) else {
+            panic!("expected midnight to be ambiguous");
+        };
+        assert_eq!(start_of_day(day(2023, 11, 5), &havana), earliest);
+    }
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!(
+            parse_timezone("Europe/Paris"),
+            Some(chrono_tz::Europe::Paris)
+        );
+        assert_eq!(parse_timezone("Mars/Olympus"), None);
+    }
 }